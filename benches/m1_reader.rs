@@ -0,0 +1,111 @@
+// Compare the typed M1 reader with the previous string based parsing
+// Run with `cargo bench --bench m1_reader`
+use histdatascraper::data::reader::{ReadOptions, read_m1, read_m1_zip};
+
+use chrono::{Duration, NaiveDate};
+use polars::prelude::*;
+use std::{
+    fs::{File, create_dir_all, remove_dir_all},
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+use ::zip::{ZipWriter, write::SimpleFileOptions};
+
+// About one year of bars
+const BARS: i64 = 372_000;
+const RUNS: usize = 5;
+
+fn main() {
+    let dir = std::env::temp_dir().join("histdata_m1_bench");
+    create_dir_all(&dir).unwrap();
+    let csv_path = dir.join("DAT_ASCII_EURUSD_M1_2019.csv");
+    let zip_path = dir.join("HISTDATA_COM_ASCII_EURUSD_M12019.zip");
+    write_sample(&csv_path, &zip_path);
+
+    let strings = bench("string columns + replace_all + cast", || parse_as_strings(&csv_path));
+    let typed = bench("typed reader from the csv file", || {
+        read_m1(BufReader::new(File::open(&csv_path).unwrap())).unwrap()
+    });
+    bench("typed reader from the zip, read in memory", || read_m1_zip(&zip_path, "EURUSD", 2019, &ReadOptions::default()).unwrap().0);
+
+    assert!(strings.equals(&typed), "Both parsers must give the same bars");
+    remove_dir_all(&dir).unwrap();
+}
+
+fn bench(name: &str, run: impl Fn() -> DataFrame) -> DataFrame {
+    let mut best = f64::MAX;
+    let mut df = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        df = Some(run());
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+
+    let df = df.unwrap();
+    println!("{:<48} {:>8.1} ms  {:>8.0} bars/s", name, best * 1000.0, df.height() as f64 / best);
+    df
+}
+
+fn write_sample(csv_path: &Path, zip_path: &Path) {
+    let start = NaiveDate::from_ymd_opt(2019, 1, 1).unwrap().and_hms_opt(17, 0, 0).unwrap();
+    let mut csv = BufWriter::new(File::create(csv_path).unwrap());
+    for i in 0..BARS {
+        let datetime = start + Duration::minutes(i);
+        let price = 1.1 + (i % 1000) as f64 * 0.00001;
+        writeln!(
+            csv,
+            "{};{:.6};{:.6};{:.6};{:.6};0",
+            datetime.format("%Y%m%d %H%M%S"),
+            price,
+            price + 0.0002,
+            price - 0.0002,
+            price + 0.0001,
+        )
+        .unwrap();
+    }
+    csv.flush().unwrap();
+    drop(csv);
+
+    let mut zip = ZipWriter::new(File::create(zip_path).unwrap());
+    zip.start_file("DAT_ASCII_EURUSD_M1_2019.csv", SimpleFileOptions::default()).unwrap();
+    std::io::copy(&mut File::open(csv_path).unwrap(), &mut zip).unwrap();
+    zip.start_file("DAT_ASCII_EURUSD_M1_2019.txt", SimpleFileOptions::default()).unwrap();
+    zip.write_all(b"Gap of 63s found between 20190101170000 and 20190101170103.\n").unwrap();
+    zip.finish().unwrap();
+}
+
+// The parsing used before the typed reader
+fn parse_as_strings(csv_path: &Path) -> DataFrame {
+    let schema = Schema::from_iter((1..=6).map(|i| Field::new(format!("column_{}", i).into(), DataType::String)));
+    let number = |name: &str, dtype: DataType| col(name).str().replace_all(lit(" "), lit(""), false).cast(dtype);
+
+    LazyCsvReader::new(csv_path)
+        .with_separator(b';')
+        .with_has_header(false)
+        .with_schema(Some(Arc::new(schema)))
+        .finish()
+        .unwrap()
+        .select([
+            col("column_1")
+                .str()
+                .to_datetime(
+                    Some(TimeUnit::Microseconds),
+                    None,
+                    StrptimeOptions {
+                        format: Some("%Y%m%d %H%M%S".into()),
+                        ..Default::default()
+                    },
+                    lit("raise"),
+                )
+                .alias("datetime"),
+            number("column_2", DataType::Float64).alias("open"),
+            number("column_3", DataType::Float64).alias("high"),
+            number("column_4", DataType::Float64).alias("low"),
+            number("column_5", DataType::Float64).alias("close"),
+            number("column_6", DataType::Int64).alias("volume"),
+        ])
+        .collect()
+        .unwrap()
+}
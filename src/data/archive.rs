@@ -0,0 +1,214 @@
+use flate2::Crc;
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+use zip::{ZipArchive, result::ZipError};
+
+// Limits checked before and while reading an archive
+// A year of M1 bars is about 25 MB, so the defaults leave a wide margin
+// while stopping a zip bomb long before it fills the memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArchiveLimits {
+    pub max_entries: usize,
+    // Uncompressed size of a single entry, in bytes
+    pub max_entry_size: u64,
+    // Uncompressed size of all the entries, in bytes
+    pub max_total_size: u64,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        ArchiveLimits {
+            max_entries: 16,
+            max_entry_size: 256 * 1024 * 1024,
+            max_total_size: 512 * 1024 * 1024,
+        }
+    }
+}
+
+// The entries of a HistData archive, read in memory
+#[derive(Clone, Debug, Default)]
+pub struct HistDataArchive {
+    // Content of the data entry, such as `DAT_ASCII_<PAIR>_M1_<YEAR>.csv`
+    pub data: Vec<u8>,
+    // Content of the status reports shipped next to the data
+    pub reports: Vec<String>,
+}
+
+// Name of the data entry of a yearly M1 archive
+pub fn expected_entry_name(pair: &str, year: usize) -> String {
+    format!("DAT_ASCII_{}_M1_{}.csv", pair.to_uppercase(), year)
+}
+
+// Name of the data entry of a monthly tick archive
+pub fn expected_tick_entry_name(pair: &str, year: i32, month: u32) -> String {
+    format!("DAT_ASCII_{}_T_{}{:02}.csv", pair.to_uppercase(), year, month)
+}
+
+// Read a HistData archive without extracting anything to disk
+// Every entry must have a path inside the archive, stay within the limits
+// and match its CRC, and the only data entry must have the expected name
+pub fn read_archive(path: &Path, expected_name: &str, limits: &ArchiveLimits) -> Result<HistDataArchive, String> {
+    let zip_error = |e: ZipError| format!("Failed to read zip {}: {}", path.display(), e);
+
+    let file = File::open(path).map_err(|e| format!("Failed to open file {}: {}", path.display(), e))?;
+    let mut archive = ZipArchive::new(BufReader::new(file)).map_err(zip_error)?;
+
+    if archive.len() > limits.max_entries {
+        return Err(format!("Zip {} has {} entries, more than the limit of {}", path.display(), archive.len(), limits.max_entries));
+    }
+
+    let mut data = None;
+    let mut reports = Vec::new();
+    let mut total_size = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(zip_error)?;
+        if entry.is_dir() {
+            continue;
+        }
+
+        // `enclosed_name` refuses absolute paths and paths going up with `..`
+        let entry_path = entry.enclosed_name()
+            .ok_or_else(|| format!("Zip {} has an entry with an unsafe path: {}", path.display(), entry.name()))?;
+        let name = entry_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+        // Check the declared size first, then the bytes actually read
+        // since the header can lie about it
+        if entry.size() > limits.max_entry_size {
+            return Err(format!("Entry {} of zip {} is {} bytes, more than the limit of {}", name, path.display(), entry.size(), limits.max_entry_size));
+        }
+        let mut content = Vec::with_capacity(entry.size() as usize);
+        (&mut entry)
+            .take(limits.max_entry_size + 1)
+            .read_to_end(&mut content)
+            .map_err(|e| format!("Failed to read entry {} of zip {}: {}", name, path.display(), e))?;
+        if content.len() as u64 > limits.max_entry_size {
+            return Err(format!("Entry {} of zip {} is larger than the limit of {} bytes", name, path.display(), limits.max_entry_size));
+        }
+
+        total_size += content.len() as u64;
+        if total_size > limits.max_total_size {
+            return Err(format!("Zip {} is larger than the limit of {} bytes once extracted", path.display(), limits.max_total_size));
+        }
+
+        let mut crc = Crc::new();
+        crc.update(&content);
+        if crc.sum() != entry.crc32() {
+            return Err(format!("Entry {} of zip {} is corrupted: CRC mismatch", name, path.display()));
+        }
+
+        let lowercase_name = name.to_lowercase();
+        if name.eq_ignore_ascii_case(expected_name) {
+            data = Some(content);
+        } else if lowercase_name.ends_with(".csv") {
+            return Err(format!("Zip {} has an unexpected data entry {}, expected {}", path.display(), name, expected_name));
+        } else if lowercase_name.ends_with(".txt") {
+            reports.push(String::from_utf8_lossy(&content).to_string());
+        }
+    }
+
+    let data = data.ok_or_else(|| format!("Zip {} does not contain {}", path.display(), expected_name))?;
+
+    Ok(HistDataArchive { data, reports })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use tempfile::TempDir;
+    use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+    const NAME: &str = "DAT_ASCII_EURUSD_M1_2019.csv";
+    const BARS: &[u8] = b"20190101 170000;1.146400;1.146600;1.146300;1.146500;0\n";
+
+    // Stored entries, so the content can be found and altered in the zip bytes
+    fn zip_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            zip.start_file(*name, SimpleFileOptions::default().compression_method(CompressionMethod::Stored)).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn read(bytes: &[u8], limits: &ArchiveLimits) -> Result<HistDataArchive, String> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("HISTDATA_COM_ASCII_EURUSD_M12019.zip");
+        std::fs::write(&path, bytes).unwrap();
+        read_archive(&path, NAME, limits)
+    }
+
+    #[test]
+    fn a_valid_archive_is_read() {
+        let bytes = zip_bytes(&[(NAME, BARS), ("DAT_ASCII_EURUSD_M1_2019.txt", b"Gap of 63s found")]);
+        let archive = read(&bytes, &ArchiveLimits::default()).unwrap();
+
+        assert_eq!(archive.data, BARS);
+        assert_eq!(archive.reports, ["Gap of 63s found"]);
+    }
+
+    #[test]
+    fn an_entry_escaping_the_archive_is_refused() {
+        let bytes = zip_bytes(&[("../DAT_ASCII_EURUSD_M1_2019.csv", BARS)]);
+        let error = read(&bytes, &ArchiveLimits::default()).unwrap_err();
+
+        assert!(error.contains("unsafe path"), "{}", error);
+    }
+
+    #[test]
+    fn an_entry_over_the_size_limit_is_refused() {
+        let bytes = zip_bytes(&[(NAME, BARS)]);
+        let limits = ArchiveLimits { max_entry_size: BARS.len() as u64 - 1, ..Default::default() };
+        let error = read(&bytes, &limits).unwrap_err();
+
+        assert!(error.contains("more than the limit"), "{}", error);
+    }
+
+    #[test]
+    fn entries_over_the_total_size_limit_are_refused() {
+        let bytes = zip_bytes(&[(NAME, BARS), ("DAT_ASCII_EURUSD_M1_2019.txt", BARS)]);
+        let limits = ArchiveLimits { max_total_size: BARS.len() as u64 * 2 - 1, ..Default::default() };
+        let error = read(&bytes, &limits).unwrap_err();
+
+        assert!(error.contains("once extracted"), "{}", error);
+    }
+
+    #[test]
+    fn too_many_entries_are_refused() {
+        let bytes = zip_bytes(&[(NAME, BARS), ("DAT_ASCII_EURUSD_M1_2019.txt", b"")]);
+        let limits = ArchiveLimits { max_entries: 1, ..Default::default() };
+        let error = read(&bytes, &limits).unwrap_err();
+
+        assert!(error.contains("2 entries"), "{}", error);
+    }
+
+    #[test]
+    fn a_corrupted_entry_is_refused() {
+        let mut bytes = zip_bytes(&[(NAME, BARS)]);
+        let start = bytes.windows(BARS.len()).position(|window| window == BARS).unwrap();
+        bytes[start] = b'3';
+        let error = read(&bytes, &ArchiveLimits::default()).unwrap_err();
+
+        // The zip crate can notice the checksum while reading, before our own check
+        assert!(error.contains("CRC mismatch") || error.contains("checksum"), "{}", error);
+    }
+
+    #[test]
+    fn an_unexpected_data_entry_is_refused() {
+        let bytes = zip_bytes(&[(NAME, BARS), ("DAT_ASCII_GBPUSD_M1_2019.csv", BARS)]);
+        let error = read(&bytes, &ArchiveLimits::default()).unwrap_err();
+
+        assert!(error.contains("unexpected data entry DAT_ASCII_GBPUSD_M1_2019.csv"), "{}", error);
+    }
+
+    #[test]
+    fn a_missing_data_entry_is_refused() {
+        let bytes = zip_bytes(&[("DAT_ASCII_EURUSD_M1_2019.txt", b"")]);
+        let error = read(&bytes, &ArchiveLimits::default()).unwrap_err();
+
+        assert!(error.contains("does not contain"), "{}", error);
+    }
+}
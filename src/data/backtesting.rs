@@ -0,0 +1,291 @@
+use crate::data::{
+    csv::{CsvDatetimeFormat, CsvOptions, write_csv},
+    output::{PendingFile, WriteMode},
+    pairs::price_digits,
+    volume::with_volume,
+};
+
+use chrono::DateTime;
+use polars::prelude::*;
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use ::zip::{ZipWriter, result::ZipError, write::SimpleFileOptions};
+
+// Column layouts expected by backtesting frameworks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BacktestPreset {
+    // `GenericCSVData` defaults, with a header
+    Backtrader,
+    // Text import of minute bars, stamped at the end of the bar
+    NinjaTrader,
+    // QuantConnect LEAN forex quote bars, one zip per day
+    Lean,
+    // Zipline `csvdir` bundle
+    Zipline,
+}
+
+impl BacktestPreset {
+    pub fn all() -> Vec<BacktestPreset> {
+        vec![
+            BacktestPreset::Backtrader,
+            BacktestPreset::NinjaTrader,
+            BacktestPreset::Lean,
+            BacktestPreset::Zipline,
+        ]
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BacktestPreset::Backtrader | BacktestPreset::Zipline => "csv",
+            BacktestPreset::NinjaTrader => "txt",
+            BacktestPreset::Lean => "zip",
+        }
+    }
+}
+
+impl fmt::Display for BacktestPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BacktestPreset::Backtrader => "backtrader",
+            BacktestPreset::NinjaTrader => "ninjatrader",
+            BacktestPreset::Lean => "quantconnect lean",
+            BacktestPreset::Zipline => "zipline",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+// Where the bars of a preset are written, the directory of the day files for LEAN
+pub fn backtest_path(data_dir: &Path, pair: &str, preset: BacktestPreset) -> PathBuf {
+    match preset {
+        // NinjaTrader names the files after the instrument and the price type
+        BacktestPreset::Backtrader => data_dir.join(format!("{}_backtrader.csv", pair)),
+        BacktestPreset::NinjaTrader => data_dir.join(format!("{}.Last.txt", pair)),
+        BacktestPreset::Lean => data_dir.join("lean").join("forex").join("oanda").join("minute").join(pair.to_lowercase()),
+        // `csvdir` bundles read `<dir>/minute/<SYMBOL>.csv`
+        BacktestPreset::Zipline => data_dir.join("zipline").join("minute").join(format!("{}.csv", pair)),
+    }
+}
+
+// Write the bars in the layout of a backtesting framework, one chunk at a time
+// Bars without prices are left out, every timestamp stays in UTC
+// The chunks must be sorted and must not overlap
+// Each file is written through a `PendingFile`, the existing ones cannot be merged
+pub struct BacktestWriter<'a> {
+    data_dir: &'a Path,
+    pair: &'a str,
+    preset: BacktestPreset,
+    write_mode: WriteMode,
+    // The open file of the CSV presets, until the first chunk for the header
+    file: Option<(PendingFile, BufWriter<File>)>,
+    first: bool,
+    // LEAN day still being filled, it can span two chunks
+    lean_day: Option<(i64, String)>,
+}
+
+impl<'a> BacktestWriter<'a> {
+    pub fn new(data_dir: &'a Path, pair: &'a str, preset: BacktestPreset, write_mode: WriteMode) -> Result<Self, String> {
+        let file = match preset {
+            BacktestPreset::Lean => None,
+            _ => {
+                let (pending, file) = create_file(backtest_path(data_dir, pair, preset), write_mode)?;
+                Some((pending, BufWriter::new(file)))
+            }
+        };
+
+        Ok(BacktestWriter {
+            data_dir,
+            pair,
+            preset,
+            write_mode,
+            file,
+            first: true,
+            lean_day: None,
+        })
+    }
+
+    pub fn write(&mut self, df: &DataFrame) -> Result<(), String> {
+        let preset = self.preset;
+        let prepare_error = |e: PolarsError| format!("Failed to prepare {} bars: {}", preset, e);
+
+        // Every preset has a volume, zero if the column was dropped
+        let df = with_volume(df)?.lazy()
+            .filter(col("close").is_not_null())
+            .collect()
+            .map_err(prepare_error)?;
+        let header = self.first;
+        self.first = false;
+
+        let (df, options) = match preset {
+            BacktestPreset::Backtrader => {
+                let df = df.lazy()
+                    .with_column(lit(0i64).alias("openinterest"))
+                    .collect()
+                    .map_err(prepare_error)?;
+                let options = CsvOptions {
+                    header,
+                    datetime_format: CsvDatetimeFormat::Custom("%Y-%m-%d %H:%M:%S".to_string()),
+                    ..Default::default()
+                }
+                .with_columns(&[
+                    ("datetime", "datetime"),
+                    ("open", "open"),
+                    ("high", "high"),
+                    ("low", "low"),
+                    ("close", "close"),
+                    ("volume", "volume"),
+                    ("openinterest", "openinterest"),
+                ]);
+                (df, options)
+            }
+            BacktestPreset::NinjaTrader => {
+                let df = df.lazy()
+                    .with_column(col("datetime") + lit(60_000_000i64).cast(DataType::Duration(TimeUnit::Microseconds)))
+                    .collect()
+                    .map_err(prepare_error)?;
+                let options = CsvOptions {
+                    delimiter: b';',
+                    header: false,
+                    datetime_format: CsvDatetimeFormat::Custom("%Y%m%d %H%M%S".to_string()),
+                    ..Default::default()
+                }
+                .with_columns(&[
+                    ("datetime", "datetime"),
+                    ("open", "open"),
+                    ("high", "high"),
+                    ("low", "low"),
+                    ("close", "close"),
+                    ("volume", "volume"),
+                ]);
+                (df, options)
+            }
+            BacktestPreset::Lean => return self.write_lean(&df),
+            BacktestPreset::Zipline => {
+                let df = df.lazy()
+                    .with_columns([lit(0.0f64).alias("dividend"), lit(1.0f64).alias("split")])
+                    .collect()
+                    .map_err(prepare_error)?;
+                let options = CsvOptions {
+                    header,
+                    datetime_format: CsvDatetimeFormat::Custom("%Y-%m-%d %H:%M:%S".to_string()),
+                    ..Default::default()
+                }
+                .with_columns(&[
+                    ("datetime", "date"),
+                    ("open", "open"),
+                    ("high", "high"),
+                    ("low", "low"),
+                    ("close", "close"),
+                    ("volume", "volume"),
+                    ("dividend", "dividend"),
+                    ("split", "split"),
+                ]);
+                (df, options)
+            }
+        };
+
+        write_csv(&df, &mut self.file.as_mut().unwrap().1, &options)
+    }
+
+    // Returns the written file, or the directory for LEAN
+    pub fn finish(mut self) -> Result<PathBuf, String> {
+        if let Some((pending, mut file)) = self.file.take() {
+            file.flush().map_err(|e| format!("Failed to write {}: {}", pending.path().display(), e))?;
+            drop(file);
+            return pending.commit();
+        }
+
+        let pair_dir = backtest_path(self.data_dir, self.pair, self.preset);
+        if let Some((day, lines)) = self.lean_day.take() {
+            write_lean_day(&pair_dir, self.pair, day, &lines, self.write_mode)?;
+        }
+
+        Ok(pair_dir)
+    }
+
+    // LEAN reads forex minute bars from `forex/oanda/minute/<pair>/YYYYMMDD_quote.zip`
+    // Each zip holds `YYYYMMDD_<pair>_minute_quote.csv` with the milliseconds since midnight UTC,
+    // then the bid and ask OHLC; HistData only has one price, so bid and ask are the same
+    fn write_lean(&mut self, df: &DataFrame) -> Result<(), String> {
+        let pair_dir = backtest_path(self.data_dir, self.pair, self.preset);
+        let digits = price_digits(self.pair) as usize;
+        let mut bars = df.clone().lazy()
+            .select([
+                col("datetime").dt().timestamp(TimeUnit::Milliseconds).alias("datetime"),
+                col("open").cast(DataType::Float64),
+                col("high").cast(DataType::Float64),
+                col("low").cast(DataType::Float64),
+                col("close").cast(DataType::Float64),
+            ])
+            .collect()
+            .map_err(|e| format!("Failed to prepare LEAN bars: {}", e))?;
+        bars.as_single_chunk_par();
+
+        let column = |name: &str| bars.column(name).map_err(|e| format!("Missing column {}: {}", name, e));
+        let times = column("datetime")?.i64().map_err(|e| e.to_string())?.clone();
+        let prices = ["open", "high", "low", "close"].iter()
+            .map(|name| Ok(column(name)?.f64().map_err(|e| e.to_string())?.clone()))
+            .collect::<Result<Vec<Float64Chunked>, String>>()?;
+
+        // The bars are sorted, so each day is written once its last bar is reached
+        for i in 0..bars.height() {
+            let Some(time) = times.get(i) else { continue };
+            let bar_day = time.div_euclid(86_400_000);
+            if self.lean_day.as_ref().is_some_and(|(day, _)| *day != bar_day) {
+                let (day, lines) = self.lean_day.take().unwrap();
+                write_lean_day(&pair_dir, self.pair, day, &lines, self.write_mode)?;
+            }
+
+            let bid = prices.iter()
+                .map(|price| format!("{:.*}", digits, price.get(i).unwrap_or_default()))
+                .collect::<Vec<String>>()
+                .join(",");
+            let (_, lines) = self.lean_day.get_or_insert_with(|| (bar_day, String::new()));
+            lines.push_str(&format!("{},{},{}\n", time.rem_euclid(86_400_000), bid, bid));
+        }
+
+        Ok(())
+    }
+}
+
+// Write all the bars in the layout of a backtesting framework
+// Returns the written file, or the directory for LEAN
+pub fn write_backtest(df: &DataFrame, data_dir: &Path, pair: &str, preset: BacktestPreset, write_mode: WriteMode) -> Result<PathBuf, String> {
+    let mut writer = BacktestWriter::new(data_dir, pair, preset, write_mode)?;
+    writer.write(df)?;
+    writer.finish()
+}
+
+fn write_lean_day(pair_dir: &Path, pair: &str, day: i64, lines: &str, write_mode: WriteMode) -> Result<(), String> {
+    let date = DateTime::from_timestamp(day * 86_400, 0)
+        .ok_or_else(|| format!("Invalid day {}", day))?
+        .format("%Y%m%d")
+        .to_string();
+    let (pending, file) = create_file(pair_dir.join(format!("{}_quote.zip", date)), write_mode)?;
+    let zip_error = |e: ZipError| format!("Failed to write {}: {}", pending.path().display(), e);
+
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    zip.start_file(format!("{}_{}_minute_quote.csv", date, pair.to_lowercase()), SimpleFileOptions::default()).map_err(zip_error)?;
+    zip.write_all(lines.as_bytes()).map_err(|e| format!("Failed to write {}: {}", pending.path().display(), e))?;
+    zip.finish().map_err(zip_error)?
+        .flush()
+        .map_err(|e| format!("Failed to write {}: {}", pending.path().display(), e))?;
+
+    pending.commit()?;
+
+    Ok(())
+}
+
+// The layouts of the frameworks cannot be read back, so they are only replaced
+fn create_file(file_path: PathBuf, write_mode: WriteMode) -> Result<(PendingFile, File), String> {
+    if write_mode == WriteMode::Merge && file_path.exists() {
+        return Err(format!("Merging into {} is not supported, overwrite it instead", file_path.display()));
+    }
+
+    PendingFile::create(file_path, write_mode)
+}
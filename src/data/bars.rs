@@ -0,0 +1,311 @@
+use crate::data::{
+    archive::{ArchiveLimits, expected_tick_entry_name, read_archive},
+    handler::HISTDATA_UTC_OFFSET_HOURS,
+    prices::{PRICE_COLUMNS, stored_as_points},
+};
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{io::BufRead, path::Path};
+
+// One minute bar, the time being the start of the minute in UTC
+// The volume is zero when the column was dropped
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bar {
+    pub time: DateTime<Utc>,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    #[serde(default)]
+    pub volume: i64,
+}
+
+// One quote of a HistData tick file, the time being UTC
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tick {
+    pub time: DateTime<Utc>,
+    pub bid: f64,
+    pub ask: f64,
+    #[serde(default)]
+    pub volume: i64,
+}
+
+// Iterator over the bars of a DataFrame, see `iter_bars`
+pub struct Bars {
+    datetimes: Int64Chunked,
+    prices: [Float64Chunked; 4],
+    volumes: Option<Int64Chunked>,
+    index: usize,
+}
+
+impl Iterator for Bars {
+    type Item = Result<Bar, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.datetimes.len() {
+            return None;
+        }
+        let i = self.index;
+        self.index += 1;
+
+        let Some(micros) = self.datetimes.get(i) else {
+            return Some(Err(format!("Bar {} has no datetime", i)));
+        };
+        let Some(time) = DateTime::from_timestamp_micros(micros) else {
+            return Some(Err(format!("Bar {} has an invalid datetime: {} µs", i, micros)));
+        };
+        // The minutes left empty by the gap filling have no prices
+        let mut prices = [0.0; 4];
+        for ((name, column), price) in PRICE_COLUMNS.iter().zip(&self.prices).zip(prices.iter_mut()) {
+            match column.get(i) {
+                Some(value) => *price = value,
+                None => return Some(Err(format!("Bar at {} has no {} price, drop or fill the empty minutes first", time, name))),
+            }
+        }
+        let [open, high, low, close] = prices;
+        let volume = self.volumes.as_ref().and_then(|volumes| volumes.get(i)).unwrap_or_default();
+
+        Some(Ok(Bar { time, open, high, low, close, volume }))
+    }
+}
+
+// Iterate over the bars of a DataFrame from the pipeline
+// A bar without a price or with an invalid datetime is yielded as an error
+// Prices stored as points must be restored first with `restore_prices`
+pub fn iter_bars(df: &DataFrame) -> Result<Bars, String> {
+    let read_error = |e: PolarsError| format!("Failed to read bars: {}", e);
+
+    if stored_as_points(df) {
+        return Err("The prices are stored as points, restore them with restore_prices first".into());
+    }
+
+    // Single chunks make the access by index cheap
+    let column = |name: &str, dtype: &DataType| -> Result<Column, String> {
+        let column = df.column(name).and_then(|c| c.cast(dtype)).map_err(read_error)?;
+        Ok(column.rechunk())
+    };
+
+    let datetimes = column("datetime", &DataType::Datetime(TimeUnit::Microseconds, None))?
+        .cast(&DataType::Int64)
+        .and_then(|c| c.i64().cloned())
+        .map_err(read_error)?;
+    let price = |name: &str| column(name, &DataType::Float64)?.f64().cloned().map_err(read_error);
+    let prices = [price("open")?, price("high")?, price("low")?, price("close")?];
+    let volumes = match df.column("volume") {
+        Ok(_) => Some(column("volume", &DataType::Int64)?.i64().cloned().map_err(read_error)?),
+        Err(_) => None,
+    };
+
+    Ok(Bars { datetimes, prices, volumes, index: 0 })
+}
+
+pub fn bars_from_dataframe(df: &DataFrame) -> Result<Vec<Bar>, String> {
+    iter_bars(df)?.collect()
+}
+
+// Build a DataFrame with the columns of the pipeline
+pub fn bars_to_dataframe(bars: &[Bar]) -> Result<DataFrame, String> {
+    let datetime = Column::new("datetime".into(), bars.iter().map(|bar| bar.time.timestamp_micros()).collect::<Vec<i64>>())
+        .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+        .map_err(|e| format!("Failed to build datetime column: {}", e))?;
+
+    DataFrame::new(vec![
+        datetime,
+        Column::new("open".into(), bars.iter().map(|bar| bar.open).collect::<Vec<f64>>()),
+        Column::new("high".into(), bars.iter().map(|bar| bar.high).collect::<Vec<f64>>()),
+        Column::new("low".into(), bars.iter().map(|bar| bar.low).collect::<Vec<f64>>()),
+        Column::new("close".into(), bars.iter().map(|bar| bar.close).collect::<Vec<f64>>()),
+        Column::new("volume".into(), bars.iter().map(|bar| bar.volume).collect::<Vec<i64>>()),
+    ])
+    .map_err(|e| format!("Failed to build bars: {}", e))
+}
+
+// A tick without a datetime, a bid or an ask is an error, like in `iter_bars`
+pub fn ticks_from_dataframe(df: &DataFrame) -> Result<Vec<Tick>, String> {
+    let read_error = |e: PolarsError| format!("Failed to read ticks: {}", e);
+
+    let datetimes = df.column("datetime")
+        .and_then(|c| c.cast(&DataType::Datetime(TimeUnit::Microseconds, None)))
+        .and_then(|c| c.cast(&DataType::Int64))
+        .map_err(read_error)?;
+    let bids = df.column("bid").and_then(|c| c.cast(&DataType::Float64)).map_err(read_error)?;
+    let asks = df.column("ask").and_then(|c| c.cast(&DataType::Float64)).map_err(read_error)?;
+    let volumes = df.column("volume").and_then(|c| c.cast(&DataType::Int64)).ok();
+
+    let bids = bids.f64().map_err(read_error)?;
+    let asks = asks.f64().map_err(read_error)?;
+    let volumes = volumes.as_ref().map(|c| c.i64()).transpose().map_err(read_error)?;
+    datetimes.i64()
+        .map_err(read_error)?
+        .iter()
+        .zip(bids.iter())
+        .zip(asks.iter())
+        .enumerate()
+        .map(|(i, ((micros, bid), ask))| {
+            let micros = micros.ok_or_else(|| format!("Tick {} has no datetime", i))?;
+            let time = DateTime::from_timestamp_micros(micros).ok_or_else(|| format!("Tick {} has an invalid datetime: {} µs", i, micros))?;
+
+            Ok(Tick {
+                time,
+                bid: bid.ok_or_else(|| format!("Tick at {} has no bid price", time))?,
+                ask: ask.ok_or_else(|| format!("Tick at {} has no ask price", time))?,
+                volume: volumes.and_then(|volumes| volumes.get(i)).unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+pub fn ticks_to_dataframe(ticks: &[Tick]) -> Result<DataFrame, String> {
+    let datetime = Column::new("datetime".into(), ticks.iter().map(|tick| tick.time.timestamp_micros()).collect::<Vec<i64>>())
+        .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+        .map_err(|e| format!("Failed to build datetime column: {}", e))?;
+
+    DataFrame::new(vec![
+        datetime,
+        Column::new("bid".into(), ticks.iter().map(|tick| tick.bid).collect::<Vec<f64>>()),
+        Column::new("ask".into(), ticks.iter().map(|tick| tick.ask).collect::<Vec<f64>>()),
+        Column::new("volume".into(), ticks.iter().map(|tick| tick.volume).collect::<Vec<i64>>()),
+    ])
+    .map_err(|e| format!("Failed to build ticks: {}", e))
+}
+
+// Iterator over the ticks of a HistData ASCII tick file, see `read_ticks`
+pub struct TickReader<R> {
+    reader: R,
+    line: Vec<u8>,
+    line_number: usize,
+}
+
+impl<R: BufRead> Iterator for TickReader<R> {
+    type Item = Result<Tick, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => self.line_number += 1,
+                Err(e) => return Some(Err(format!("Failed to read tick data: {}", e))),
+            }
+
+            let line = String::from_utf8_lossy(self.line.trim_ascii());
+            if line.is_empty() {
+                continue;
+            }
+
+            return Some(parse_tick(&line).ok_or_else(|| format!("Invalid tick on line {}: '{}'", self.line_number, line)));
+        }
+    }
+}
+
+// Read the ticks of a HistData ASCII tick file one at a time
+// The lines look like `20190101 170014817,1.146130,1.146590,0`
+// and the times are converted from the source timezone to UTC
+pub fn read_ticks<R: BufRead>(reader: R) -> TickReader<R> {
+    TickReader { reader, line: Vec::with_capacity(64), line_number: 0 }
+}
+
+// Read the ticks of a monthly HistData tick archive
+pub fn read_ticks_zip(path: &Path, pair: &str, year: i32, month: u32, limits: &ArchiveLimits) -> Result<Vec<Tick>, String> {
+    let archive = read_archive(path, &expected_tick_entry_name(pair, year, month), limits)?;
+
+    read_ticks(archive.data.as_slice())
+        .collect::<Result<Vec<Tick>, String>>()
+        .map_err(|e| format!("{} in {}", e, path.display()))
+}
+
+fn parse_tick(line: &str) -> Option<Tick> {
+    let mut fields = line.split(',').map(str::trim);
+
+    let time = NaiveDateTime::parse_from_str(fields.next()?, "%Y%m%d %H%M%S%3f").ok()?;
+    let time = (time - Duration::hours(HISTDATA_UTC_OFFSET_HOURS)).and_utc();
+    let bid = fields.next()?.parse().ok()?;
+    let ask = fields.next()?.parse().ok()?;
+    let volume = fields.next().map_or(Some(0), |volume| volume.parse().ok())?;
+
+    Some(Tick { time, bid, ask, volume })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::prices::{PriceStorage, compact_prices};
+
+    fn sample_bars() -> Vec<Bar> {
+        let time = DateTime::from_timestamp(1_577_836_740, 0).unwrap();
+
+        vec![
+            Bar { time, open: 1.1212, high: 1.1213, low: 1.12115, close: 1.12127, volume: 0 },
+            Bar { time: time + Duration::minutes(1), open: 1.12127, high: 1.1214, low: 1.12125, close: 1.12131, volume: 3 },
+        ]
+    }
+
+    #[test]
+    fn bars_round_trip_through_a_dataframe() {
+        let df = bars_to_dataframe(&sample_bars()).unwrap();
+
+        assert_eq!(df.column("datetime").unwrap().dtype(), &DataType::Datetime(TimeUnit::Microseconds, None));
+        assert_eq!(bars_from_dataframe(&df).unwrap(), sample_bars());
+        assert_eq!(bars_from_dataframe(&df.drop("volume").unwrap()).unwrap()[1].volume, 0);
+    }
+
+    #[test]
+    fn a_bar_without_price_is_an_error() {
+        let mut df = bars_to_dataframe(&sample_bars()).unwrap();
+        df.with_column(Column::new("close".into(), [Some(1.12127), None])).unwrap();
+        let bars = iter_bars(&df).unwrap().collect::<Vec<_>>();
+
+        assert_eq!(bars[0], Ok(sample_bars()[0]));
+        assert!(bars[1].as_ref().unwrap_err().contains("no close price"), "{:?}", bars[1]);
+    }
+
+    #[test]
+    fn points_are_refused() {
+        let df = compact_prices(&bars_to_dataframe(&sample_bars()).unwrap(), "EURUSD", PriceStorage::Points).unwrap();
+
+        assert!(iter_bars(&df).is_err());
+    }
+
+    #[test]
+    fn bars_and_ticks_round_trip_through_json() {
+        let json = serde_json::to_string(&sample_bars()).unwrap();
+        assert!(json.contains("\"time\":\"2019-12-31T23:59:00Z\""), "{}", json);
+        assert_eq!(serde_json::from_str::<Vec<Bar>>(&json).unwrap(), sample_bars());
+
+        // The volume is optional
+        let tick = serde_json::from_str::<Tick>(r#"{"time":"2019-01-01T22:00:14.817Z","bid":1.14613,"ask":1.14659}"#).unwrap();
+        assert_eq!(tick.volume, 0);
+        assert_eq!(serde_json::from_str::<Tick>(&serde_json::to_string(&tick).unwrap()).unwrap(), tick);
+    }
+
+    #[test]
+    fn ticks_are_read_in_utc() {
+        let ticks = read_ticks("20190101 170014817,1.146130,1.146590,0\n\nbad line\n".as_bytes()).collect::<Vec<_>>();
+
+        let tick = ticks[0].as_ref().unwrap();
+        assert_eq!(tick.time.to_rfc3339(), "2019-01-01T22:00:14.817+00:00");
+        assert_eq!((tick.bid, tick.ask), (1.14613, 1.14659));
+        assert_eq!(ticks[1], Err("Invalid tick on line 3: 'bad line'".to_string()));
+        assert_eq!(ticks_from_dataframe(&ticks_to_dataframe(&[*tick]).unwrap()).unwrap(), [*tick]);
+    }
+
+    #[test]
+    fn a_tick_without_price_is_an_error() {
+        let tick = Tick { time: DateTime::from_timestamp(1_546_380_014, 0).unwrap(), bid: 1.14613, ask: 1.14659, volume: 0 };
+        let df = ticks_to_dataframe(&[tick, tick]).unwrap();
+
+        let missing = |name: &str| {
+            let mut df = df.clone();
+            let column = df.column(name).unwrap().as_materialized_series().clone();
+            let mask = BooleanChunked::new("mask".into(), [true, false]);
+            df.with_column(column.zip_with(&mask, &Series::full_null(name.into(), 2, column.dtype())).unwrap()).unwrap();
+            ticks_from_dataframe(&df).unwrap_err()
+        };
+
+        assert_eq!(missing("datetime"), "Tick 1 has no datetime");
+        assert_eq!(missing("bid"), "Tick at 2019-01-01 22:00:14 UTC has no bid price");
+        assert_eq!(missing("ask"), "Tick at 2019-01-01 22:00:14 UTC has no ask price");
+    }
+}
@@ -0,0 +1,184 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Weekday};
+use chrono_tz::America::New_York;
+use polars::prelude::*;
+
+// A day without trading, repeated every year
+// The trading day runs from 17:00 New York the day before to 17:00 New York
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Holiday {
+    pub name: String,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Holiday {
+    pub fn new(name: &str, month: u32, day: u32) -> Self {
+        Holiday { name: name.to_string(), month, day }
+    }
+}
+
+// When the FX market is open
+// Sunday 17:00 to Friday 17:00 New York time, DST included,
+// minus the holidays
+#[derive(Clone, Debug)]
+pub struct MarketCalendar {
+    pub holidays: Vec<Holiday>,
+}
+
+impl Default for MarketCalendar {
+    fn default() -> Self {
+        MarketCalendar {
+            holidays: vec![
+                Holiday::new("New Year", 1, 1),
+                Holiday::new("Christmas", 12, 25),
+            ],
+        }
+    }
+}
+
+impl MarketCalendar {
+    // A calendar with only the weekly closure
+    pub fn without_holidays() -> Self {
+        MarketCalendar { holidays: Vec::new() }
+    }
+
+    // The New York trading day a UTC time belongs to
+    // The day rolls over at 17:00 New York
+    pub fn trading_day(&self, datetime: NaiveDateTime) -> NaiveDate {
+        let local = New_York.from_utc_datetime(&datetime).naive_local();
+
+        if local.time() >= NaiveTime::from_hms_opt(17, 0, 0).unwrap() {
+            local.date().succ_opt().unwrap()
+        } else {
+            local.date()
+        }
+    }
+
+    pub fn is_holiday(&self, day: NaiveDate) -> bool {
+        self.holidays.iter().any(|holiday| holiday.month == day.month() && holiday.day == day.day())
+    }
+
+    // Whether the market is open at a UTC time
+    pub fn is_open(&self, datetime: NaiveDateTime) -> bool {
+        let day = self.trading_day(datetime);
+
+        !matches!(day.weekday(), Weekday::Sat | Weekday::Sun) && !self.is_holiday(day)
+    }
+
+    // Count the minutes the market was open strictly between two UTC times
+    // A gap with no open minute is an expected closure
+    pub fn open_minutes_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> i64 {
+        // Start on the first whole minute after `from`
+        let mut minute = from.with_second(0).unwrap().with_nanosecond(0).unwrap() + Duration::minutes(1);
+        let mut count = 0;
+
+        while minute < to {
+            if self.is_open(minute) {
+                count += 1;
+            }
+            minute += Duration::minutes(1);
+        }
+
+        count
+    }
+
+    pub fn is_expected_closure(&self, from: NaiveDateTime, to: NaiveDateTime) -> bool {
+        self.open_minutes_between(from, to) == 0
+    }
+
+    // Drop the bars stamped while the market is closed
+    // HistData sometimes has a few stray bars during the weekend
+    pub fn drop_closed_bars(&self, df: &DataFrame) -> Result<DataFrame, String> {
+        let datetimes = df.column("datetime")
+            .and_then(|c| c.datetime())
+            .map_err(|e| format!("Failed to read datetimes: {}", e))?;
+
+        let mask: BooleanChunked = datetimes
+            .into_iter()
+            .map(|micros| {
+                micros
+                    .and_then(chrono::DateTime::from_timestamp_micros)
+                    .is_some_and(|dt| self.is_open(dt.naive_utc()))
+            })
+            .collect();
+
+        df.filter(&mask).map_err(|e| format!("Failed to drop closed bars: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(datetime: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    // The first and last open minutes in UTC around a weekend
+    fn assert_weekend(calendar: &MarketCalendar, last_open: &str, first_open: &str) {
+        let (last_open, first_open) = (utc(last_open), utc(first_open));
+
+        assert!(calendar.is_open(last_open), "{} should be open", last_open);
+        assert!(!calendar.is_open(last_open + Duration::minutes(1)), "{} should be closed", last_open + Duration::minutes(1));
+        assert!(!calendar.is_open(first_open - Duration::minutes(1)), "{} should be closed", first_open - Duration::minutes(1));
+        assert!(calendar.is_open(first_open), "{} should be open", first_open);
+    }
+
+    #[test]
+    fn the_week_follows_new_york_daylight_saving() {
+        let calendar = MarketCalendar::default();
+
+        // March 8 2020 starts the daylight saving on a Sunday
+        assert_weekend(&calendar, "2020-02-28 21:59", "2020-03-01 22:00");
+        assert_weekend(&calendar, "2020-03-06 21:59", "2020-03-08 21:00");
+        assert_weekend(&calendar, "2020-03-13 20:59", "2020-03-15 21:00");
+
+        // November 1 2020 ends it on a Sunday
+        assert_weekend(&calendar, "2020-10-30 20:59", "2020-11-01 22:00");
+        assert_weekend(&calendar, "2020-11-06 21:59", "2020-11-08 22:00");
+    }
+
+    #[test]
+    fn the_trading_day_rolls_over_at_five_pm_new_york() {
+        let calendar = MarketCalendar::default();
+
+        assert_eq!(calendar.trading_day(utc("2020-01-06 21:59")), NaiveDate::from_ymd_opt(2020, 1, 6).unwrap());
+        assert_eq!(calendar.trading_day(utc("2020-01-06 22:00")), NaiveDate::from_ymd_opt(2020, 1, 7).unwrap());
+        assert_eq!(calendar.trading_day(utc("2020-07-06 21:00")), NaiveDate::from_ymd_opt(2020, 7, 7).unwrap());
+    }
+
+    #[test]
+    fn a_holiday_closes_its_trading_day() {
+        let calendar = MarketCalendar::default();
+
+        // Christmas 2020 is a Friday, its trading day starts on Thursday evening
+        assert_weekend(&calendar, "2020-12-24 21:59", "2020-12-27 22:00");
+        assert!(MarketCalendar::without_holidays().is_open(utc("2020-12-25 12:00")));
+        assert!(calendar.is_holiday(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap()));
+    }
+
+    #[test]
+    fn a_weekend_has_no_open_minute() {
+        let calendar = MarketCalendar::default();
+
+        // 21:59 on Friday, then 22:00 and 22:01 on Sunday
+        assert_eq!(calendar.open_minutes_between(utc("2020-01-10 21:58"), utc("2020-01-12 22:02")), 3);
+        assert!(calendar.is_expected_closure(utc("2020-01-10 21:59"), utc("2020-01-12 22:00")));
+        assert!(!calendar.is_expected_closure(utc("2020-01-10 21:58"), utc("2020-01-12 22:00")));
+        assert_eq!(calendar.open_minutes_between(utc("2020-01-07 10:00"), utc("2020-01-07 11:00")), 59);
+    }
+
+    #[test]
+    fn the_bars_of_a_closed_market_are_dropped() {
+        let micros = ["2020-01-10 21:59", "2020-01-11 12:00", "2020-01-12 21:59", "2020-01-12 22:00"]
+            .map(|datetime| utc(datetime).and_utc().timestamp_micros());
+        let df = DataFrame::new(vec![
+            Column::new("datetime".into(), micros.to_vec()).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap(),
+            Column::new("close".into(), [1.0, 2.0, 3.0, 4.0]),
+        ])
+        .unwrap();
+        let df = MarketCalendar::default().drop_closed_bars(&df).unwrap();
+
+        assert_eq!(df.column("close").unwrap().f64().unwrap().into_no_null_iter().collect::<Vec<f64>>(), [1.0, 4.0]);
+    }
+}
@@ -0,0 +1,190 @@
+use polars::prelude::*;
+use std::io::Write;
+
+// How the datetime column is written
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum CsvDatetimeFormat {
+    // Polars default, ISO 8601 with microseconds
+    #[default]
+    Default,
+    // ISO 8601 to the second, `2019-01-02T17:00:00`
+    Iso,
+    // Any chrono format string
+    Custom(String),
+    // Milliseconds since 1970-01-01 UTC
+    EpochMillis,
+    // Seconds since 1970-01-01 UTC
+    EpochSeconds,
+    // Separate `date` and `time` columns with their chrono formats
+    SplitDateTime {
+        date_format: String,
+        time_format: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    pub header: bool,
+    // Columns to write, in order, as (column, output name)
+    // Every column is written with its own name when empty
+    // With split date and time, the columns are named `date` and `time`
+    pub columns: Vec<(String, String)>,
+    pub datetime_format: CsvDatetimeFormat,
+    // Number of decimals of the prices, as many as needed when `None`
+    pub float_precision: Option<usize>,
+    pub line_ending: LineEnding,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            header: true,
+            columns: Vec::new(),
+            datetime_format: CsvDatetimeFormat::default(),
+            float_precision: None,
+            line_ending: LineEnding::default(),
+        }
+    }
+}
+
+impl CsvOptions {
+    // Shortcut to pick and rename columns
+    pub fn with_columns(mut self, columns: &[(&str, &str)]) -> Self {
+        self.columns = columns.iter().map(|(column, name)| (column.to_string(), name.to_string())).collect();
+        self
+    }
+}
+
+// Reshape the DataFrame for the CSV dialect
+// The datetime is converted, then the columns are picked and renamed
+pub fn prepare_csv(df: &DataFrame, options: &CsvOptions) -> Result<DataFrame, String> {
+    let mut columns = Vec::new();
+
+    for name in df.get_column_names() {
+        if name != "datetime" {
+            columns.push(col(name.clone()));
+            continue;
+        }
+
+        let datetime = col("datetime");
+        match &options.datetime_format {
+            CsvDatetimeFormat::EpochMillis => columns.push(datetime.dt().timestamp(TimeUnit::Milliseconds).alias("datetime")),
+            // Bars are on whole minutes, so the division is exact
+            CsvDatetimeFormat::EpochSeconds => columns.push(
+                (datetime.dt().timestamp(TimeUnit::Milliseconds) / lit(1000i64)).cast(DataType::Int64).alias("datetime"),
+            ),
+            CsvDatetimeFormat::SplitDateTime { date_format, time_format } => {
+                columns.push(datetime.clone().dt().strftime(date_format).alias("date"));
+                columns.push(datetime.dt().strftime(time_format).alias("time"));
+            }
+            _ => columns.push(datetime),
+        }
+    }
+
+    let mut lazy = df.clone().lazy().select(columns);
+    if !options.columns.is_empty() {
+        lazy = lazy.select(
+            options.columns.iter()
+                .map(|(column, name)| col(column.as_str()).alias(name.as_str()))
+                .collect::<Vec<Expr>>(),
+        );
+    }
+
+    lazy.collect().map_err(|e| format!("Failed to prepare CSV columns: {}", e))
+}
+
+// Write the DataFrame as CSV with the given dialect
+pub fn write_csv<W: Write>(df: &DataFrame, writer: W, options: &CsvOptions) -> Result<(), String> {
+    let mut df = prepare_csv(df, options)?;
+
+    let datetime_format = match &options.datetime_format {
+        CsvDatetimeFormat::Iso => Some("%Y-%m-%dT%H:%M:%S".to_string()),
+        CsvDatetimeFormat::Custom(format) => Some(format.clone()),
+        _ => None,
+    };
+    let line_terminator = match options.line_ending {
+        LineEnding::Lf => "\n",
+        LineEnding::CrLf => "\r\n",
+    };
+
+    CsvWriter::new(writer)
+        .with_separator(options.delimiter)
+        .include_header(options.header)
+        .with_datetime_format(datetime_format)
+        .with_float_precision(options.float_precision)
+        .with_line_terminator(line_terminator.to_string())
+        .finish(&mut df)
+        .map_err(|e| format!("Failed to write CSV file: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2019-01-02 17:00 and 17:01
+    fn sample_bars() -> DataFrame {
+        DataFrame::new(vec![
+            Column::new("datetime".into(), [1_546_448_400_000_000i64, 1_546_448_460_000_000]).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap(),
+            Column::new("close".into(), [1.1464, 1.14655]),
+            Column::new("volume".into(), [0i64, 3]),
+        ])
+        .unwrap()
+    }
+
+    fn written(options: CsvOptions) -> String {
+        let mut bytes = Vec::new();
+        write_csv(&sample_bars(), &mut bytes, &options).unwrap();
+
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn the_default_dialect() {
+        assert_eq!(
+            written(CsvOptions::default()),
+            "datetime,close,volume\n2019-01-02T17:00:00.000000,1.1464,0\n2019-01-02T17:01:00.000000,1.14655,3\n",
+        );
+    }
+
+    #[test]
+    fn the_delimiter_header_and_line_ending() {
+        let options = CsvOptions { delimiter: b';', header: false, line_ending: LineEnding::CrLf, ..Default::default() };
+
+        assert_eq!(written(options), "2019-01-02T17:00:00.000000;1.1464;0\r\n2019-01-02T17:01:00.000000;1.14655;3\r\n");
+    }
+
+    #[test]
+    fn the_datetime_formats() {
+        let first_line = |datetime_format: CsvDatetimeFormat| {
+            written(CsvOptions { datetime_format, header: false, ..Default::default() }).lines().next().unwrap().to_string()
+        };
+
+        assert_eq!(first_line(CsvDatetimeFormat::Iso), "2019-01-02T17:00:00,1.1464,0");
+        assert_eq!(first_line(CsvDatetimeFormat::Custom("%Y%m%d %H%M%S".into())), "20190102 170000,1.1464,0");
+        assert_eq!(first_line(CsvDatetimeFormat::EpochMillis), "1546448400000,1.1464,0");
+        assert_eq!(first_line(CsvDatetimeFormat::EpochSeconds), "1546448400,1.1464,0");
+        let split = CsvDatetimeFormat::SplitDateTime { date_format: "%Y.%m.%d".into(), time_format: "%H:%M".into() };
+        assert_eq!(first_line(split), "2019.01.02,17:00,1.1464,0");
+    }
+
+    #[test]
+    fn the_columns_are_picked_and_renamed() {
+        let options = CsvOptions {
+            datetime_format: CsvDatetimeFormat::SplitDateTime { date_format: "%Y%m%d".into(), time_format: "%H%M".into() },
+            float_precision: Some(5),
+            ..Default::default()
+        }
+        .with_columns(&[("time", "<TIME>"), ("date", "<DATE>"), ("close", "<CLOSE>")]);
+
+        assert_eq!(written(options), "<TIME>,<DATE>,<CLOSE>\n1700,20190102,1.14640\n1701,20190102,1.14655\n");
+    }
+}
@@ -0,0 +1,167 @@
+use crate::data::{
+    output::{ParquetOptions, read_parquet, write_parquet},
+    prices::{convert_prices, recorded_storage},
+};
+
+use chrono::{DateTime, Datelike};
+use polars::{io::HiveOptions, prelude::*};
+use std::{
+    fs::{File, create_dir_all, rename},
+    path::{Path, PathBuf},
+};
+
+// Write the bars as a Hive-partitioned Parquet dataset
+// `<data_dir>/symbol=<PAIR>/year=<YYYY>/month=<MM>/part.parquet`
+// A partition that already exists is merged with the new bars,
+// the new bar wins when both have the same timestamp
+// The range of a run is replaced by the first and last bar of each partition,
+// since a merged partition holds bars of several runs
+pub fn write_partitioned(df: &DataFrame, data_dir: &Path, pair: &str, options: &ParquetOptions, metadata: &[(String, String)]) -> Result<PathBuf, String> {
+    let symbol_dir = data_dir.join(format!("symbol={}", pair));
+
+    for (year, month, partition) in split_by_month(df)? {
+        let partition_dir = symbol_dir.join(format!("year={}", year)).join(format!("month={:02}", month));
+        create_dir_all(&partition_dir).map_err(|e| format!("Failed to create directory {}: {}", partition_dir.display(), e))?;
+
+        let file_path = partition_dir.join("part.parquet");
+        let mut partition = merge_existing(&file_path, partition, pair, metadata)?;
+        let metadata = partition_metadata(&partition, metadata)?;
+
+        // Write next to the partition then rename
+        // so a reader never sees a half written file
+        let temp_path = partition_dir.join("part.parquet.tmp");
+        let file = File::create(&temp_path).map_err(|e| format!("Failed to create file {}: {}", temp_path.display(), e))?;
+        write_parquet(&mut partition, file, options, &metadata)?;
+        rename(&temp_path, &file_path).map_err(|e| format!("Failed to replace {}: {}", file_path.display(), e))?;
+    }
+
+    Ok(symbol_dir)
+}
+
+// Lazily scan a dataset written by `write_partitioned`
+// The `symbol`, `year` and `month` columns come from the paths,
+// so filters on them skip the other partitions
+pub fn scan_partitioned(data_dir: &Path) -> Result<LazyFrame, String> {
+    let args = ScanArgsParquet {
+        hive_options: HiveOptions {
+            enabled: Some(true),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    LazyFrame::scan_parquet(data_dir.join("**/*.parquet"), args)
+        .map_err(|e| format!("Failed to scan dataset {}: {}", data_dir.display(), e))
+}
+
+// Split the bars in runs of the same month
+// The bars are sorted so every month is a single slice
+fn split_by_month(df: &DataFrame) -> Result<Vec<(i32, u32, DataFrame)>, String> {
+    let months = df.column("datetime")
+        .and_then(|c| c.datetime())
+        .map_err(|e| format!("Failed to read datetimes: {}", e))?
+        .into_no_null_iter()
+        .map(|micros| {
+            let datetime = DateTime::from_timestamp_micros(micros).unwrap();
+            (datetime.year(), datetime.month())
+        })
+        .collect::<Vec<(i32, u32)>>();
+
+    let mut partitions = Vec::new();
+    let mut start = 0;
+    for i in 1..=months.len() {
+        if i == months.len() || months[i] != months[start] {
+            let (year, month) = months[start];
+            partitions.push((year, month, df.slice(start as i64, i - start)));
+            start = i;
+        }
+    }
+
+    Ok(partitions)
+}
+
+fn partition_metadata(partition: &DataFrame, metadata: &[(String, String)]) -> Result<Vec<(String, String)>, String> {
+    let datetimes = partition.column("datetime")
+        .and_then(|c| c.datetime())
+        .map_err(|e| format!("Failed to read datetimes: {}", e))?;
+    let bound = |micros: Option<i64>| micros.and_then(DateTime::from_timestamp_micros).map(|datetime| datetime.to_rfc3339()).unwrap_or_default();
+
+    Ok(metadata.iter()
+        .filter(|(key, _)| key != "requested_from" && key != "requested_to")
+        .cloned()
+        .chain([
+            ("first_bar".to_string(), bound(datetimes.min())),
+            ("last_bar".to_string(), bound(datetimes.max())),
+        ])
+        .collect())
+}
+
+// The existing prices are converted to the storage recorded for the new ones
+fn merge_existing(file_path: &Path, df: DataFrame, pair: &str, metadata: &[(String, String)]) -> Result<DataFrame, String> {
+    if !file_path.exists() {
+        return Ok(df);
+    }
+
+    let file = File::open(file_path).map_err(|e| format!("Failed to open file {}: {}", file_path.display(), e))?;
+    let storage = recorded_storage(metadata)?;
+    let existing = convert_prices(&read_parquet(file, file_path)?, None, pair, storage)
+        .map_err(|e| format!("Cannot merge {} prices with {}: {}", storage, file_path.display(), e))?;
+
+    existing
+        .vstack(&df)
+        .and_then(|merged| merged.unique_stable(Some(&["datetime".to_string()]), UniqueKeepStrategy::Last, None))
+        .and_then(|merged| merged.sort(["datetime"], Default::default()))
+        .map_err(|e| format!("Failed to merge with {}: {}", file_path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    fn bars(micros: &[i64]) -> DataFrame {
+        DataFrame::new(vec![
+            Column::new("datetime".into(), micros.to_vec()).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap(),
+            Column::new("open".into(), vec![1.1; micros.len()]),
+            Column::new("high".into(), vec![1.1; micros.len()]),
+            Column::new("low".into(), vec![1.1; micros.len()]),
+            Column::new("close".into(), vec![1.1; micros.len()]),
+        ])
+        .unwrap()
+    }
+
+    fn footer(path: &Path) -> Vec<(String, String)> {
+        let mut reader = ParquetReader::new(File::open(path).unwrap());
+
+        reader.get_metadata()
+            .unwrap()
+            .key_value_metadata()
+            .iter()
+            .flatten()
+            .map(|pair| (pair.key.clone(), pair.value.clone().unwrap_or_default()))
+            .collect()
+    }
+
+    #[test]
+    fn a_merged_partition_records_its_own_range() {
+        let dir = tempdir().unwrap();
+        let metadata = [
+            ("symbol".to_string(), "EURUSD".to_string()),
+            ("requested_from".to_string(), "2020-01-01T00:00:00+00:00".to_string()),
+            ("requested_to".to_string(), "2020-01-01T00:00:00+00:00".to_string()),
+        ];
+
+        // Two runs touching the same month
+        write_partitioned(&bars(&[1_577_836_800_000_000]), dir.path(), "EURUSD", &ParquetOptions::default(), &metadata).unwrap();
+        write_partitioned(&bars(&[1_578_268_800_000_000]), dir.path(), "EURUSD", &ParquetOptions::default(), &metadata).unwrap();
+
+        let footer = footer(&dir.path().join("symbol=EURUSD/year=2020/month=01/part.parquet"));
+        let value = |key: &str| footer.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str());
+        assert_eq!(value("symbol"), Some("EURUSD"));
+        assert_eq!(value("first_bar"), Some("2020-01-01T00:00:00+00:00"));
+        assert_eq!(value("last_bar"), Some("2020-01-06T00:00:00+00:00"));
+        assert_eq!(value("requested_from"), None);
+        assert_eq!(value("requested_to"), None);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::data::calendar::MarketCalendar;
+
+use chrono::{DateTime, Duration, NaiveDateTime};
+use polars::prelude::*;
+
+// What to do with the minutes without any bar
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FillPolicy {
+    // Keep the bars as downloaded
+    #[default]
+    Disabled,
+    // Add the missing minutes with null prices
+    LeaveNull,
+    // Add the missing minutes as flat bars at the previous close with zero volume
+    ForwardFill,
+    // Same as `ForwardFill`, with an `is_filled` column marking the added bars
+    Flag,
+}
+
+// Reindex the bars to every minute the market is open
+// between the first and the last bar, then fill the new rows
+// Bars stamped while the market is closed are kept
+pub fn fill_gaps(df: &DataFrame, policy: FillPolicy, calendar: &MarketCalendar) -> Result<DataFrame, String> {
+    if policy == FillPolicy::Disabled || df.height() == 0 {
+        return Ok(df.clone());
+    }
+
+    let bars = df.column("datetime")
+        .and_then(|c| c.datetime())
+        .map_err(|e| format!("Failed to read datetimes: {}", e))?
+        .into_no_null_iter()
+        .collect::<Vec<i64>>();
+
+    // The grid is every open minute plus the existing bars
+    let to_datetime = |micros: i64| DateTime::from_timestamp_micros(micros).unwrap().naive_utc();
+    let mut grid = open_minutes(to_datetime(bars[0]), to_datetime(bars[bars.len() - 1]), calendar)
+        .into_iter()
+        .map(|minute| minute.and_utc().timestamp_micros())
+        .chain(bars.iter().copied())
+        .collect::<Vec<i64>>();
+    grid.sort_unstable();
+    grid.dedup();
+
+    let grid = Column::new("datetime".into(), grid)
+        .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+        .and_then(|column| DataFrame::new(vec![column]))
+        .map_err(|e| format!("Failed to build minute grid: {}", e))?;
+
+    let mut filled = grid.lazy()
+        .left_join(df.clone().lazy(), col("datetime"), col("datetime"))
+        .with_column(col("close").is_null().alias("is_filled"));
+
+    // Flat bars at the previous close
+    if policy != FillPolicy::LeaveNull {
+        let close = col("close").forward_fill(None);
+
+        filled = filled.with_columns([
+            col("open").fill_null(close.clone()),
+            col("high").fill_null(close.clone()),
+            col("low").fill_null(close.clone()),
+            close.alias("close"),
+            col("volume").fill_null(lit(0i64)),
+        ]);
+    }
+
+    if policy != FillPolicy::Flag {
+        filled = filled.drop(["is_filled"]);
+    }
+
+    filled.collect().map_err(|e| format!("Failed to fill gaps: {}", e))
+}
+
+// Every minute the market is open between two UTC times, both included
+fn open_minutes(from: NaiveDateTime, to: NaiveDateTime, calendar: &MarketCalendar) -> Vec<NaiveDateTime> {
+    let mut minutes = Vec::new();
+
+    let mut minute = from;
+    while minute <= to {
+        if calendar.is_open(minute) {
+            minutes.push(minute);
+        }
+        minute += Duration::minutes(1);
+    }
+
+    minutes
+}
@@ -0,0 +1,174 @@
+use crate::data::{calendar::MarketCalendar, handler::HISTDATA_UTC_OFFSET_HOURS, output::WriteMode, report::{remove_report, save_report}};
+
+use chrono::{Duration, NaiveDateTime};
+use polars::prelude::*;
+use std::path::{Path, PathBuf};
+
+// A period without any data, as reported by HistData
+// The dates are UTC
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gap {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub duration: Duration,
+    // Whether the market was closed during the whole gap
+    pub expected: bool,
+}
+
+impl Gap {
+    pub fn new(start: NaiveDateTime, end: NaiveDateTime) -> Self {
+        Gap { start, end, duration: end - start, expected: false }
+    }
+}
+
+// Parse a status report
+// The gaps are lines like `Gap of 63s found between 20190101170000 and 20190101170103.`
+// and every other line is ignored
+pub fn parse_gap_report(content: &str) -> Vec<Gap> {
+    let source_offset = Duration::hours(HISTDATA_UTC_OFFSET_HOURS);
+
+    content
+        .lines()
+        .filter_map(|line| {
+            let (_, dates) = line.trim().split_once(" found between ")?;
+            let (start, end) = dates.trim_end_matches('.').split_once(" and ")?;
+
+            let start = NaiveDateTime::parse_from_str(start.trim(), "%Y%m%d%H%M%S").ok()?;
+            let end = NaiveDateTime::parse_from_str(end.trim(), "%Y%m%d%H%M%S").ok()?;
+
+            // The report uses the same timezone as the data
+            Some(Gap::new(start - source_offset, end - source_offset))
+        })
+        .collect()
+}
+
+// Sort the gaps and merge the ones that overlap
+// Useful when the reports of consecutive years are put together
+pub fn merge_gaps(mut gaps: Vec<Gap>) -> Vec<Gap> {
+    gaps.sort_by_key(|gap| gap.start);
+
+    let mut merged: Vec<Gap> = Vec::with_capacity(gaps.len());
+    for gap in gaps {
+        match merged.last_mut() {
+            Some(last) if gap.start <= last.end => {
+                if gap.end > last.end {
+                    *last = Gap::new(last.start, gap.end);
+                }
+            }
+            _ => merged.push(gap),
+        }
+    }
+
+    merged
+}
+
+// Tell apart the market closures from the real gaps
+pub fn classify_gaps(gaps: &mut [Gap], calendar: &MarketCalendar) {
+    for gap in gaps {
+        gap.expected = calendar.is_expected_closure(gap.start, gap.end);
+    }
+}
+
+// Only keep the gaps overlapping the range
+pub fn gaps_in_range(gaps: Vec<Gap>, from_date: NaiveDateTime, to_date: NaiveDateTime) -> Vec<Gap> {
+    gaps.into_iter()
+        .filter(|gap| gap.end >= from_date && gap.start <= to_date)
+        .collect()
+}
+
+pub fn gaps_to_dataframe(gaps: &[Gap]) -> Result<DataFrame, String> {
+    let to_micros = |date: &NaiveDateTime| date.and_utc().timestamp_micros();
+    let datetime_type = DataType::Datetime(TimeUnit::Microseconds, None);
+
+    let start = Column::new("start".into(), gaps.iter().map(|gap| to_micros(&gap.start)).collect::<Vec<i64>>());
+    let end = Column::new("end".into(), gaps.iter().map(|gap| to_micros(&gap.end)).collect::<Vec<i64>>());
+    let duration = Column::new("duration_seconds".into(), gaps.iter().map(|gap| gap.duration.num_seconds()).collect::<Vec<i64>>());
+    let expected = Column::new("expected".into(), gaps.iter().map(|gap| gap.expected).collect::<Vec<bool>>());
+
+    start.cast(&datetime_type)
+        .and_then(|start| Ok(vec![start, end.cast(&datetime_type)?, duration, expected]))
+        .and_then(DataFrame::new)
+        .map_err(|e| format!("Failed to build gap DataFrame: {}", e))
+}
+
+// Write the gaps next to the data as `<PAIR>_gaps.csv`
+// Nothing is written when there is no gap, and the file of a previous run is removed
+// The existing file follows the write mode of the data
+pub fn save_gaps(gaps: &[Gap], data_dir: &Path, pair: &str, write_mode: WriteMode) -> Result<Option<PathBuf>, String> {
+    if gaps.is_empty() {
+        remove_report(data_dir, pair, "gaps", write_mode)?;
+        return Ok(None);
+    }
+
+    save_report(&mut gaps_to_dataframe(gaps)?, data_dir, pair, "gaps", write_mode).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn utc(datetime: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn gap(start: &str, end: &str) -> Gap {
+        Gap::new(utc(start), utc(end))
+    }
+
+    #[test]
+    fn the_reported_gaps_are_shifted_to_utc() {
+        let report = "HistData.com status report\n\
+            Gap of 63s found between 20190101170000 and 20190101170103.\n\
+            Gap of 1m found between 2019010117 and 20190101170200.\n\
+            \n\
+            Gap of 120s found between 20191231230000 and 20191231230200.\n";
+        let gaps = parse_gap_report(report);
+
+        assert_eq!(gaps, [gap("2019-01-01 22:00:00", "2019-01-01 22:01:03"), gap("2020-01-01 04:00:00", "2020-01-01 04:02:00")]);
+        assert_eq!(gaps[0].duration, Duration::seconds(63));
+    }
+
+    #[test]
+    fn overlapping_gaps_are_merged() {
+        let gaps = merge_gaps(vec![
+            gap("2020-01-02 10:00:00", "2020-01-02 11:00:00"),
+            gap("2020-01-01 10:00:00", "2020-01-01 12:00:00"),
+            gap("2020-01-01 11:00:00", "2020-01-01 13:00:00"),
+            gap("2020-01-01 11:30:00", "2020-01-01 11:45:00"),
+            gap("2020-01-01 13:00:00", "2020-01-01 13:30:00"),
+        ]);
+
+        assert_eq!(gaps, [gap("2020-01-01 10:00:00", "2020-01-01 13:30:00"), gap("2020-01-02 10:00:00", "2020-01-02 11:00:00")]);
+        assert_eq!(gaps[0].duration, Duration::minutes(210));
+    }
+
+    #[test]
+    fn the_gaps_touching_the_range_are_kept() {
+        let gaps = vec![
+            gap("2020-01-01 00:00:00", "2020-01-01 10:00:00"),
+            gap("2020-01-01 09:00:00", "2020-01-01 09:30:00"),
+            gap("2020-01-02 10:00:00", "2020-01-02 11:00:00"),
+            gap("2020-01-02 11:00:01", "2020-01-02 12:00:00"),
+        ];
+        let kept = gaps_in_range(gaps.clone(), utc("2020-01-01 10:00:00"), utc("2020-01-02 11:00:00"));
+
+        assert_eq!(kept, [gaps[0].clone(), gaps[2].clone()]);
+    }
+
+    #[test]
+    fn the_report_of_a_previous_run_is_removed() {
+        let dir = tempdir().unwrap();
+        let gaps = [gap("2020-01-01 10:00:00", "2020-01-01 11:00:00")];
+        let path = save_gaps(&gaps, dir.path(), "EURUSD", WriteMode::ErrorIfExists).unwrap().unwrap();
+        assert_eq!(path, dir.path().join("EURUSD_gaps.csv"));
+
+        // The report of a previous run is only touched when the data can be
+        assert!(save_gaps(&gaps, dir.path(), "EURUSD", WriteMode::ErrorIfExists).unwrap_err().contains("already exists"));
+        assert!(save_gaps(&[], dir.path(), "EURUSD", WriteMode::ErrorIfExists).unwrap_err().contains("already exists"));
+        assert_eq!(save_gaps(&gaps, dir.path(), "EURUSD", WriteMode::Overwrite).unwrap(), Some(path.clone()));
+
+        assert_eq!(save_gaps(&[], dir.path(), "EURUSD", WriteMode::Merge).unwrap(), None);
+        assert!(!path.exists());
+    }
+}
@@ -1,113 +1,549 @@
+use crate::{NUMBER_OF_SIMULTANEOUS_TASKS, PAIRS};
+use crate::data::{
+    fill::fill_gaps,
+    gaps::{Gap, classify_gaps, gaps_in_range, merge_gaps, save_gaps},
+    options::{Backend, CancelToken, DownloadOptions, RangeEnd},
+    reader::read_m1_zip,
+    output::{OutputFormat, report_write_mode, save_staged},
+    sessions::{filter_sessions, tag_sessions},
+    staging::{StagingDir, read_staged, scan_staged, stage},
+    summary::DownloadSummary,
+    validation::{save_validation_report, validate_bars},
+    volume::{VolumePolicy, add_tick_counts, is_zero_volume},
+};
 use crate::utils::{
     driver::{close_driver, launch_driver},
-    utils::{calculate_progress_weight, calculate_split, find_available_port, get_download_dir, unzip_file, wait_until_file_downloaded},
+    utils::{calculate_progress_weight, calculate_split, find_available_port, get_download_dir, wait_until_file_downloaded},
 };
 
-use chrono::{Datelike, DateTime, Utc};
-use futures::future::join_all;
+use chrono::{Datelike, DateTime, Duration, NaiveDateTime, NaiveTime, Utc};
 use polars::prelude::*;
 use std::{
-    fs::{File, remove_dir_all},
-    path::PathBuf,
+    collections::{BTreeMap, VecDeque},
+    fs::{copy, create_dir_all, remove_file, rename},
+    path::{Path, PathBuf},
     sync::Arc,
 };
 use thirtyfour::prelude::*;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+
+// HistData timestamps are Eastern Standard Time without daylight saving
+// So they are always 5 hours behind UTC
+pub const HISTDATA_UTC_OFFSET_HOURS: i64 = -5;
+
+pub async fn download_data(pair: String, from_date: DateTime<Utc>, to_date: DateTime<Utc>, data_dir: PathBuf, format: OutputFormat, options: DownloadOptions, tx: Sender<usize>) -> Result<DownloadSummary, String> {
+    let years = years_to_download(&pair, from_date, to_date).await;
+
+    // Each parsed year is written to the staging directory
+    // instead of being kept in memory
+    let staging_dir = StagingDir::create(&data_dir, &pair)?;
+    let tick_dir = options.volume.tick_archive_dir(Some(&data_dir))?;
+    let mut workers = spawn_downloads(&pair, &years, staging_dir.path(), &options, tx.clone());
+
+    // Process the years one after the other
+    // while the next ones are still downloading
+    let mut pipeline = YearPipeline::new(&pair, &years, (from_date, to_date), &tick_dir, &options);
+    let processed = process_years(&mut pipeline, &mut workers, staging_dir.path()).await;
+
+    // The workers are stopped when the processing fails, and waited for
+    // so they don't write to the staging directory once it is removed
+    workers.stop().await;
+    let mut processed = processed?;
+    if options.cancel.is_cancelled() {
+        return Err("The download was cancelled".to_string());
+    }
+    let (mut summary, gaps) = pipeline.into_summary();
+
+    // The volume is only known to be useless once every year is processed
+    if options.volume.policy == VolumePolicy::DropIfZero && summary.zero_volume {
+        for path in processed.iter_mut() {
+            let mut df = read_staged(path)?.drop("volume").map_err(|e| format!("Failed to drop volume: {}", e))?;
+            *path = stage(&mut df, &path.with_extension("no_volume.parquet"))?;
+        }
+        summary.volume_dropped = true;
+    }
+
+    // The validation report covers every year
+    // In strict mode any issue fails the run, but the report is still written
+    if options.validation.enabled {
+        summary.validation_file_path = save_validation_report(&summary.issues, &data_dir, &pair, report_write_mode(format, options.output.write_mode))?;
+
+        if let Some(validation_file_path) = &summary.validation_file_path
+            && options.validation.strict {
+            return Err(format!("Validation found {} issues, see {}", summary.issues.len(), validation_file_path.display()));
+        }
+    }
 
-pub async fn download_data(pair: String, from_date: DateTime<Utc>, to_date: DateTime<Utc>, data_dir: PathBuf, data_type: String, tx: Sender<usize>) -> Result<(), String> {
-    // Calculate the number of years to download
-    let from_year = from_date.year() as usize;
-    let to_year = to_date.year() as usize;
-    let year_duration = to_year - from_year + 1 as usize;
+    // Merge the gaps before saving
+    // so their summary is part of the metadata
+    summary.gaps = gaps_in_range(merge_gaps(gaps), from_date.naive_utc(), to_date.naive_utc());
+    classify_gaps(&mut summary.gaps, &options.calendar);
+
+    // Save the data, one processed year at a time
+    let metadata = summary.metadata(from_date, to_date);
+    summary.file_paths = save_staged(&processed, &data_dir, &pair, format, (from_date, to_date), &options.output, &metadata)?;
+
+    // Save the gaps next to the data
+    summary.gaps_file_path = save_gaps(&summary.gaps, &data_dir, &pair, report_write_mode(format, options.output.write_mode))?;
+
+    // Signal the progress that we have finished
+    tx.send(0).await.map_err(|_| "Failed to send progress")?;
+
+    Ok(summary)
+}
+
+// Process the staged years as they come and stage them again
+// Returns the processed files, in order
+async fn process_years(pipeline: &mut YearPipeline<'_>, workers: &mut Workers, staging_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut processed = Vec::new();
+    while let Some(staged) = workers.recv().await {
+        match staged {
+            Ok(staged) => {
+                for (_, mut df) in pipeline.push(staged)? {
+                    processed.push(stage(&mut df, &staging_dir.join(format!("processed_{}.parquet", processed.len())))?);
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    for (_, mut df) in pipeline.finish()? {
+        processed.push(stage(&mut df, &staging_dir.join(format!("processed_{}.parquet", processed.len())))?);
+    }
+
+    Ok(processed)
+}
+
+// The HistData files to download for the range
+// The files are split on the source timezone years
+// so the first hours of a UTC year are in the previous file
+pub async fn years_to_download(pair: &str, from_date: DateTime<Utc>, to_date: DateTime<Utc>) -> Vec<usize> {
+    let source_offset = Duration::hours(HISTDATA_UTC_OFFSET_HOURS);
+    let first_year = {
+        let pairs = PAIRS.lock().await;
+        pairs.get(pair).map_or(0, |date| date.year() as usize)
+    };
+    let from_year = ((from_date + source_offset).year() as usize).max(first_year);
+    let to_year = (to_date + source_offset).year() as usize;
+
+    (from_year..=to_year).collect()
+}
+
+// A year parsed by a worker and written to the staging directory
+pub struct StagedYear {
+    pub year: usize,
+    pub path: PathBuf,
+    // Gaps reported in the archive of the year
+    pub gaps: Vec<Gap>,
+}
+
+// Progress sent to the terminal
+// Each year counts twice, once downloaded and once parsed
+pub struct Progress {
+    tx: Sender<usize>,
+    weight: usize,
+}
+
+impl Progress {
+    async fn step(&self) -> Result<(), String> {
+        self.tx.send(self.weight).await.map_err(|_| "Failed to send progress".to_string())
+    }
+}
+
+// The workers started by `spawn_downloads`
+pub struct Workers {
+    staged: Receiver<Result<StagedYear, String>>,
+    // Child of the token of the options, to stop these workers only
+    stop: CancelToken,
+}
+
+impl Workers {
+    // The next staged year, in no particular order, or the error of a worker
+    // `None` once every worker is done
+    pub async fn recv(&mut self) -> Option<Result<StagedYear, String>> {
+        self.staged.recv().await
+    }
+
+    // Stop the workers before their next year and wait until they are done,
+    // so nothing is written to the staging directory once it is removed
+    pub async fn stop(mut self) {
+        self.stop.cancel();
+        while self.staged.recv().await.is_some() {}
+    }
+}
+
+// Spawn the workers downloading the years in parallel
+// Each year is sent once staged, in no particular order, along with the errors of the workers
+pub fn spawn_downloads(pair: &str, years: &[usize], staging_dir: &Path, options: &DownloadOptions, tx: Sender<usize>) -> Workers {
+    let (staged_tx, staged_rx) = channel(years.len().max(1));
+    let stop = options.cancel.child();
 
     // Then we split it to make it parallel
-    let split = calculate_split(from_year, year_duration);
+    let tasks = options.concurrency.unwrap_or(NUMBER_OF_SIMULTANEOUS_TASKS).max(1);
+    let split = match years.first() {
+        Some(&from_year) => calculate_split(from_year, years.len(), tasks),
+        None => Vec::new(),
+    };
 
     // Calculate the progress weight
     // So we have a good display of the progress
-    let progress_weight = Arc::new(calculate_progress_weight(year_duration));
-
-    // Get the default download directory
-    let download_dir = get_download_dir().unwrap();
+    let progress = Arc::new(Progress { tx, weight: calculate_progress_weight(years.len()) });
 
-    // Init the main DataFrame
-    let mut main_df = DataFrame::new(vec![
-        Column::new("datetime".into(), Vec::<i64>::new()).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap(),
-        Column::new("open".into(), Vec::<f64>::new()),
-        Column::new("high".into(), Vec::<f64>::new()),
-        Column::new("low".into(), Vec::<f64>::new()),
-        Column::new("close".into(), Vec::<f64>::new()),
-        Column::new("volume".into(), Vec::<i64>::new()),
-    ]).unwrap();
-    
     // Create arc to permit sharing the data between threads
-    let pair = Arc::new(pair);
-    let download_dir = Arc::new(download_dir);
-    let tx = Arc::new(tx);
+    let pair = Arc::new(pair.to_string());
+    let staging_path = Arc::new(staging_dir.to_path_buf());
+    let options = Arc::new(DownloadOptions { cancel: stop.clone(), ..options.clone() });
 
     // Create the different tasks and spawn them
-    // Store the results in tasks
-    let mut tasks = Vec::new();
-    for repartition in split {
-        let pair = Arc::clone(&pair);
-        let download_dir = Arc::clone(&download_dir);
-        let tx = Arc::clone(&tx);
-        let progress_weight = Arc::clone(&progress_weight);
-
-        tasks.push(tokio::spawn(async move {
-            download_split_data(pair, download_dir, repartition, tx, progress_weight).await
-        }));
-    }
-
-    // Wait for all the tasks to finish
-    let results = join_all(tasks).await;
-
-    // Check for errors and if not merge the DataFrames
-    for result in results {
-        match result {
-            Ok(Ok(df)) => {
-                main_df.vstack_mut(&df).unwrap();
-            }
-            Ok(Err(e)) => {
-                eprintln!("Error downloading data: {}", e);
-            }
-            Err(e) => {
-                eprintln!("Error spawning task: {}", e);
-            }
+    // A failed or crashed task is reported through the channel
+    for repartition in split.into_iter().filter(|years| !years.is_empty()) {
+        let task = download_split_data(
+            Arc::clone(&pair),
+            Arc::clone(&staging_path),
+            repartition,
+            Arc::clone(&options),
+            staged_tx.clone(),
+            Arc::clone(&progress),
+        );
+        let staged_tx = staged_tx.clone();
+
+        tokio::spawn(async move {
+            let error = match tokio::spawn(task).await {
+                Ok(Ok(())) => return,
+                Ok(Err(e)) => format!("Error downloading data: {}", e),
+                Err(e) => format!("Error spawning task: {}", e),
+            };
+            let _ = staged_tx.send(Err(error)).await;
+        });
+    }
+
+    Workers { staged: staged_rx, stop }
+}
+
+// Runs the pipeline on the staged years in chronological order,
+// each year as soon as the years before it are processed
+pub struct YearPipeline<'a> {
+    pair: &'a str,
+    range: (DateTime<Utc>, DateTime<Utc>),
+    tick_dir: &'a Path,
+    options: &'a DownloadOptions,
+    // Years not processed yet, in order
+    expected: VecDeque<usize>,
+    // Years staged before the years preceding them
+    pending: BTreeMap<usize, PathBuf>,
+    last_year: Option<usize>,
+    carry: Carry,
+    gaps: Vec<Gap>,
+    summary: DownloadSummary,
+}
+
+impl<'a> YearPipeline<'a> {
+    pub fn new(pair: &'a str, years: &[usize], range: (DateTime<Utc>, DateTime<Utc>), tick_dir: &'a Path, options: &'a DownloadOptions) -> Self {
+        YearPipeline {
+            pair,
+            range,
+            tick_dir,
+            options,
+            expected: years.iter().copied().collect(),
+            pending: BTreeMap::new(),
+            last_year: None,
+            carry: Carry::default(),
+            gaps: Vec::new(),
+            summary: DownloadSummary {
+                pair: pair.to_string(),
+                zero_volume: true,
+                ..Default::default()
+            },
         }
     }
 
+    // Hand over a staged year
+    // Returns the processed years that are ready, in order
+    pub fn push(&mut self, staged: StagedYear) -> Result<Vec<(usize, DataFrame)>, String> {
+        self.gaps.extend(staged.gaps);
+        self.pending.insert(staged.year, staged.path);
+
+        let mut ready = Vec::new();
+        while let Some(&year) = self.expected.front()
+            && let Some(path) = self.pending.remove(&year) {
+            self.expected.pop_front();
+            let df = self.read_staged_year(&path)?;
+            ready.push((year, self.process(year, df, self.expected.is_empty())?));
+        }
+
+        Ok(ready)
+    }
+
+    // Process the years left once every worker is done
+    // The years that failed to download are skipped
+    pub fn finish(&mut self) -> Result<Vec<(usize, DataFrame)>, String> {
+        let first_year = self.expected.front().copied();
+        self.expected.clear();
+
+        let mut ready = Vec::new();
+        while let Some((year, path)) = self.pending.pop_first() {
+            let df = self.read_staged_year(&path)?;
+            ready.push((year, self.process(year, df, self.pending.is_empty())?));
+        }
+
+        // Release the day held back when the last year is missing,
+        // or give empty bars when no year could be downloaded
+        if self.carry.tail.is_some() || self.last_year.is_none() {
+            let year = self.last_year.or(first_year).unwrap_or_default();
+            let empty = self.carry.tail.as_ref().map_or_else(empty_bars, |tail| tail.clear());
+            ready.push((year, self.process(year, empty, true)?));
+        }
+
+        Ok(ready)
+    }
+
+    // The counts and issues of the processed years, and the gaps reported by HistData
+    pub fn into_summary(self) -> (DownloadSummary, Vec<Gap>) {
+        (self.summary, self.gaps)
+    }
+
     // Only keep the data between the dates asked
-    main_df = main_df.lazy()
-                     .filter(col("datetime").gt(lit(from_date.timestamp_millis() * 1000)))
-                     .filter(col("datetime").lt(lit(to_date.timestamp_millis() * 1000)))
-                     .collect().map_err(|_| "Failed to filter DataFrame")?;
+    fn read_staged_year(&self, path: &Path) -> Result<DataFrame, String> {
+        let (from_date, to_date) = self.range;
 
-    // Save the data
-    save_data(&mut main_df, &data_dir, &pair, &data_type)?;
+        scan_staged(path)?
+            .filter(range_filter(from_date, to_date, self.options.range_end))
+            .collect()
+            .map_err(|e| format!("Failed to filter DataFrame: {}", e))
+    }
 
-    // Signal the progress that we have finished
-    tx.send(0).await.map_err(|_| "Failed to send progress")?;
+    fn process(&mut self, year: usize, mut df: DataFrame, last: bool) -> Result<DataFrame, String> {
+        // Count the ticks of the bars of the months with tick data
+        if self.options.volume.policy == VolumePolicy::TickCount {
+            let (counted_df, counted) = add_tick_counts(&df, self.tick_dir, self.pair, &self.options.read.archive_limits)?;
+            df = counted_df;
+            self.summary.tick_volume_bars += counted;
+        }
 
-    Ok(())
+        let df = process_chunk(df, last, &mut self.carry, self.options, &mut self.summary)?;
+        self.summary.zero_volume &= is_zero_volume(&df)?;
+        self.last_year = Some(year);
+
+        Ok(df)
+    }
 }
 
-// This function split is used to execute the different tasks in parallel
-pub async fn download_split_data(pair: Arc<String>, download_dir: Arc<String>, years: Vec<usize>, tx: Arc<Sender<usize>>, progress_weight: Arc<usize>) -> Result<DataFrame, String> {
-    // Init the main DataFrame
-    let mut main_df = DataFrame::new(vec![
+// Bars kept from a processed year for the next one
+#[derive(Default)]
+struct Carry {
+    // Last day of bars, sorted and deduplicated again with the next year
+    // so the bars repeated at file boundaries are found
+    tail: Option<DataFrame>,
+    // Last bars before filling, so the checks and the filling
+    // see the end of the previous year
+    context: Option<DataFrame>,
+}
+
+// Run every step of the pipeline on one year of bars
+// The counts and the issues are added to the summary
+fn process_chunk(df: DataFrame, last: bool, carry: &mut Carry, options: &DownloadOptions, summary: &mut DownloadSummary) -> Result<DataFrame, String> {
+    let mut df = match carry.tail.take() {
+        Some(mut tail) => {
+            tail.vstack_mut(&df).map_err(|e| format!("Failed to merge years: {}", e))?;
+            tail
+        }
+        None => df,
+    };
+
+    // Consecutive files can share bars at their boundaries
+    df = sort_and_deduplicate(df, summary)?;
+
+    // Hold back the last day until the next year is loaded
+    if !last && let Some(last_day) = last_day_start(&df)? {
+        let (head, tail) = split_at(&df, last_day)?;
+        df = head;
+        carry.tail = Some(tail);
+    }
+
+    // Drop the bars stamped while the market is closed
+    if options.drop_closed_bars {
+        let height = df.height();
+        df = options.calendar.drop_closed_bars(&df)?;
+        summary.closed_bars += height - df.height();
+    }
+
+    // The previous bars are checked again with the new ones,
+    // only the issues found after them are new
+    let context = match carry.context.take() {
+        Some(mut context) => {
+            let context_end = last_datetime(&context)?;
+            context.vstack_mut(&df).map_err(|e| format!("Failed to merge years: {}", e))?;
+            Some((context, context_end))
+        }
+        None => None,
+    };
+
+    // Check the quality of the bars
+    if options.validation.enabled {
+        let (bars, context_end) = match &context {
+            Some((bars, context_end)) => (bars, *context_end),
+            None => (&df, None),
+        };
+        let issues = validate_bars(bars, &options.validation, &options.calendar)?;
+        summary.issues.extend(issues.into_iter().filter(|issue| context_end.is_none_or(|end| issue.datetime > end)));
+    }
+
+    // Add the missing minutes
+    // so the bars are regularly spaced while the market is open
+    // The last bar of the previous year starts the grid and is removed after
+    let height = df.height();
+    let mut filled = match &context {
+        Some((bars, Some(_))) if df.height() > 0 => {
+            let anchored = bars.slice((bars.height() - height - 1) as i64, height + 1);
+            fill_gaps(&anchored, options.fill, &options.calendar)?.slice(1, usize::MAX)
+        }
+        _ => fill_gaps(&df, options.fill, &options.calendar)?,
+    };
+    summary.filled_bars += filled.height() - height;
+
+    // Keep the end of the bars for the next year
+    let window = options.validation.volatility_window.max(1);
+    carry.context = match context {
+        Some((bars, _)) => Some(bars.tail(Some(window))),
+        None => Some(df.tail(Some(window))),
+    };
+
+    // Keep or tag the trading sessions
+    // after the validation so the sessions don't show up as gaps
+    if !options.sessions.filter.is_empty() {
+        filled = filter_sessions(&filled, &options.sessions.filter)?;
+    }
+    if options.sessions.tag {
+        filled = tag_sessions(&filled, &options.sessions.tag_sessions)?;
+    }
+    summary.rows += filled.height();
+
+    Ok(filled)
+}
+
+fn last_datetime(df: &DataFrame) -> Result<Option<NaiveDateTime>, String> {
+    Ok(df.column("datetime")
+        .and_then(|c| c.datetime())
+        .map_err(|e| format!("Failed to read datetimes: {}", e))?
+        .into_no_null_iter()
+        .last()
+        .and_then(|micros| DateTime::from_timestamp_micros(micros).map(|dt| dt.naive_utc())))
+}
+
+// Midnight UTC of the last bar, in microseconds
+fn last_day_start(df: &DataFrame) -> Result<Option<i64>, String> {
+    Ok(last_datetime(df)?.map(|datetime| datetime.date().and_time(NaiveTime::MIN).and_utc().timestamp_micros()))
+}
+
+// The bars before and from the given time, the DataFrame being sorted
+fn split_at(df: &DataFrame, micros: i64) -> Result<(DataFrame, DataFrame), String> {
+    let position = df.column("datetime")
+        .and_then(|c| c.datetime())
+        .map_err(|e| format!("Failed to read datetimes: {}", e))?
+        .into_no_null_iter()
+        .take_while(|datetime| *datetime < micros)
+        .count();
+
+    Ok((df.slice(0, position), df.slice(position as i64, usize::MAX)))
+}
+
+// Empty bars with the columns of the parsed files
+fn empty_bars() -> DataFrame {
+    DataFrame::new(vec![
         Column::new("datetime".into(), Vec::<i64>::new()).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap(),
         Column::new("open".into(), Vec::<f64>::new()),
         Column::new("high".into(), Vec::<f64>::new()),
         Column::new("low".into(), Vec::<f64>::new()),
         Column::new("close".into(), Vec::<f64>::new()),
         Column::new("volume".into(), Vec::<i64>::new()),
-    ]).unwrap();
+    ]).unwrap()
+}
+
+// This function split is used to execute the different tasks in parallel
+// Each year is parsed, written to the staging directory then handed over
+pub async fn download_split_data(pair: Arc<String>, staging_dir: Arc<PathBuf>, years: Vec<usize>, options: Arc<DownloadOptions>, staged_tx: Sender<Result<StagedYear, String>>, progress: Arc<Progress>) -> Result<(), String> {
+    // The browser is only launched for the first year missing from the cache,
+    // along with the download directory it saves to
+    let mut browser: Option<(WebDriver, usize, String)> = None;
+
+    for year in years {
+        // Stop between two years when the download is cancelled
+        if options.cancel.is_cancelled() {
+            break;
+        }
+
+        let archive_name = format!("HISTDATA_COM_ASCII_{}_M1{}.zip", pair, year);
+        let cached = options.cache_dir.as_ref().map(|cache_dir| cache_dir.join(&archive_name)).filter(|path| path.exists());
+        let downloaded = cached.is_none();
+
+        let file = match cached {
+            Some(path) => path,
+            None => {
+                if browser.is_none() {
+                    let download_dir = get_download_dir()?;
+                    let (driver, port) = open_browser(options.backend).await?;
+                    browser = Some((driver, port, download_dir));
+                }
+                let (driver, _, download_dir) = browser.as_ref().unwrap();
+                let lowercase_pair = pair.to_lowercase();
+
+                // Find the download link
+                // And click on it
+                driver.get(format!("https://www.histdata.com/download-free-forex-historical-data/?/ascii/1-minute-bar-quotes/{}/{}", lowercase_pair, year)).await.map_err(|_| "Failed to open URL")?;
+                let elem = driver.find(By::Id("a_file")).await.map_err(|_| "Failed to find element: a_file")?;
+                elem.click().await.map_err(|_| "Failed to click element: a_file")?;
+
+                // Wait for the download to finish
+                let file = format!("{}/{}", download_dir, archive_name);
+                wait_until_file_downloaded(&file);
+
+                PathBuf::from(file)
+            }
+        };
+
+        // Notify that we are done with the download
+        progress.step().await?;
+
+        // Parse the bars and the status report straight from the zip
+        // The archive is checked before parsing and only removed once staged
+        let (mut df, gaps) = read_m1_zip(&file, &pair, year, &options.read)?;
+
+        // Convert the timestamps to UTC
+        df = normalize_timezone(df)?;
+
+        // Stage the year so it can leave the memory
+        let path = stage(&mut df, &staging_dir.join(format!("{}.parquet", year)))?;
+
+        // Move the downloaded file to the cache, or remove it
+        if downloaded {
+            match &options.cache_dir {
+                Some(cache_dir) => cache_archive(&file, &cache_dir.join(&archive_name))?,
+                None => remove_file(&file).map_err(|_| "Failed to remove the downloaded file")?,
+            }
+        }
+
+        // Notify that we are done with the parsing
+        progress.step().await?;
+        staged_tx.send(Ok(StagedYear { year, path, gaps })).await.map_err(|_| "Failed to hand over the staged year")?;
+    }
+
+    // Close the driver and the server
+    if let Some((driver, port, _)) = browser {
+        driver.quit().await.map_err(|_| "Failed to quit driver")?;
+        close_driver(port).await.map_err(|_| "Failed to close browser")?;
+    }
+
+    Ok(())
+}
+
+// Launch a headless browser with the backend
+// Returns the driver and the port of its server
+async fn open_browser(backend: Backend) -> Result<(WebDriver, usize), String> {
+    let Backend::ChromeDriver { ports: (from_port, to_port) } = backend;
 
     // Find an available port
     // And launch the driver
-    let port = find_available_port(9000, 9500).await;
-    launch_driver(port).await.unwrap();
+    let port = find_available_port(from_port, to_port).await;
+    launch_driver(port).await?;
 
     // Put some arguments to the driver
     let mut caps = DesiredCapabilities::chrome();
@@ -117,116 +553,261 @@ pub async fn download_split_data(pair: Arc<String>, download_dir: Arc<String>, y
     caps.add_arg("--disable-dev-shm-usage").map_err(|_| "Failed to add argument: --disable-dev-shm-usage")?;
 
     // Use the driver
-    let driver = match WebDriver::new(format!("http://localhost:{}", port), caps).await {
-        Ok(d) => d,
-        Err(_) => return Err("Failed to create WebDriver".into()),
-    };
+    match WebDriver::new(format!("http://localhost:{}", port), caps).await {
+        Ok(driver) => Ok((driver, port)),
+        Err(_) => Err("Failed to create WebDriver".into()),
+    }
+}
 
-    for year in years {
-        let lowercase_pair = pair.to_lowercase();
-
-        // Find the download link
-        // And click on it
-        driver.get(format!("https://www.histdata.com/download-free-forex-historical-data/?/ascii/1-minute-bar-quotes/{}/{}", lowercase_pair, year)).await.map_err(|_| "Failed to open URL")?;
-        let elem = driver.find(By::Id("a_file")).await.map_err(|_| "Failed to find element: a_file")?;
-        elem.click().await.map_err(|_| "Failed to click element: a_file")?;
-
-        // Wait for the download to finish
-        let file = format!("{}/HISTDATA_COM_ASCII_{}_M1{}.zip", download_dir, pair, year);
-        wait_until_file_downloaded(&file);
-        match unzip_file(&file) {
-            Ok(_) => (),
-            Err(e) => return Err(format!("Failed to unzip file: {}", e)),
-        }
+// Move a downloaded archive to the cache
+// The download directory can be on another file system
+fn cache_archive(file: &Path, cached: &Path) -> Result<(), String> {
+    if let Some(parent) = cached.parent() {
+        create_dir_all(parent).map_err(|e| format!("Failed to create directory {}: {}", parent.display(), e))?;
+    }
 
-        // Notify that we are done with the download
-        tx.send(*progress_weight).await.map_err(|_| "Failed to send progress")?;
-
-        // Force all the columns to be string
-        let schema = Schema::from_iter(vec![
-            Field::new("column_1".into(), DataType::String),
-            Field::new("column_2".into(), DataType::String),
-            Field::new("column_3".into(), DataType::String),
-            Field::new("column_4".into(), DataType::String),
-            Field::new("column_5".into(), DataType::String),
-            Field::new("column_6".into(), DataType::String),
-        ]);
-        
-        // Get the data from the file downloaded
-        let file_path = format!("{}/DAT_ASCII_{}_M1_{}.csv", &file.strip_suffix(".zip").unwrap(), pair, year);
-        let mut df = LazyCsvReader::new(&file_path).with_separator(b';').with_has_header(false)
-            .with_schema(Some(Arc::new(schema)))
-            .finish().unwrap().collect().unwrap();
-
-        // Parse all the columns
-        df = df.lazy()
-            .with_columns([
-                col("column_1")
-                .str()
-                .to_datetime(
-                    Some(TimeUnit::Microseconds),
-                    None,
-                    StrptimeOptions {
-                        format: Some("%Y%m%d %H%M%S".into()),
-                        ..Default::default()
-                    },
-                    lit("raise"),
-                )
+    if rename(file, cached).is_err() {
+        copy(file, cached).map_err(|e| format!("Failed to cache {}: {}", file.display(), e))?;
+        remove_file(file).map_err(|_| "Failed to remove the downloaded file")?;
+    }
+
+    Ok(())
+}
+
+// Shift the source timestamps to UTC
+// The column stays a naive datetime, but its values are UTC
+pub fn normalize_timezone(df: DataFrame) -> Result<DataFrame, String> {
+    df.lazy()
+        .with_column(
+            (col("datetime") - lit(Duration::hours(HISTDATA_UTC_OFFSET_HOURS)))
+                .cast(DataType::Datetime(TimeUnit::Microseconds, None))
                 .alias("datetime"),
-                col("column_2").cast(DataType::String).str().replace_all(lit(" "), lit(""), false).cast(DataType::Float64).alias("open"),
-                col("column_3").cast(DataType::String).str().replace_all(lit(" "), lit(""), false).cast(DataType::Float64).alias("high"),
-                col("column_4").cast(DataType::String).str().replace_all(lit(" "), lit(""), false).cast(DataType::Float64).alias("low"),
-                col("column_5").cast(DataType::String).str().replace_all(lit(" "), lit(""), false).cast(DataType::Float64).alias("close"),
-                col("column_6").cast(DataType::String).str().replace_all(lit(" "), lit(""), false).cast(DataType::Int64).alias("volume"),
-            ])
-        .drop([
-            "column_1",
-            "column_2",
-            "column_3",
-            "column_4",
-            "column_5",
-            "column_6",
+        )
+        .collect()
+        .map_err(|e| format!("Failed to normalize timezone: {}", e))
+}
+
+// Keep the bars between the two dates
+// The start is inclusive and the end depends on `range_end`
+// Both dates are compared to the UTC timestamps at microsecond precision
+pub fn filter_range(df: DataFrame, from_date: DateTime<Utc>, to_date: DateTime<Utc>, range_end: RangeEnd) -> Result<DataFrame, String> {
+    df.lazy()
+        .filter(range_filter(from_date, to_date, range_end))
+        .collect()
+        .map_err(|e| format!("Failed to filter DataFrame: {}", e))
+}
+
+// The predicate of `filter_range`, to filter a scan before reading it
+pub fn range_filter(from_date: DateTime<Utc>, to_date: DateTime<Utc>, range_end: RangeEnd) -> Expr {
+    let datetime_type = DataType::Datetime(TimeUnit::Microseconds, None);
+    let from_date = lit(from_date.timestamp_micros()).cast(datetime_type.clone());
+    let to_date = lit(to_date.timestamp_micros()).cast(datetime_type);
+
+    let end_filter = match range_end {
+        RangeEnd::Exclusive => col("datetime").lt(to_date),
+        RangeEnd::Inclusive => col("datetime").lt_eq(to_date),
+    };
+
+    col("datetime").gt_eq(from_date).and(end_filter)
+}
+
+// Sort the bars by time and remove the duplicated timestamps
+// so the datetime column is strictly increasing
+// Duplicates and conflicting bars are added to the summary
+pub fn sort_and_deduplicate(df: DataFrame, summary: &mut DownloadSummary) -> Result<DataFrame, String> {
+    let df = df
+        .sort(["datetime"], SortMultipleOptions::default().with_maintain_order(true))
+        .map_err(|e| format!("Failed to sort DataFrame: {}", e))?;
+
+    // Remove the bars that are exactly the same
+    let height = df.height();
+    let df = df
+        .unique_stable(None, UniqueKeepStrategy::First, None)
+        .map_err(|e| format!("Failed to remove duplicates: {}", e))?;
+    summary.duplicates += height - df.height();
+
+    // The remaining duplicated timestamps have different prices
+    let conflicts = df
+        .clone()
+        .lazy()
+        .group_by([col("datetime")])
+        .agg([len().alias("count")])
+        .filter(col("count").gt(lit(1)))
+        .sort(["datetime"], Default::default())
+        .collect()
+        .map_err(|e| format!("Failed to find conflicting bars: {}", e))?;
+    summary.conflicts.extend(conflicts
+        .column("datetime")
+        .and_then(|c| c.datetime())
+        .map_err(|e| format!("Failed to read conflicting bars: {}", e))?
+        .into_no_null_iter()
+        .filter_map(|micros| DateTime::from_timestamp_micros(micros).map(|dt| dt.naive_utc())));
+
+    // Keep the first bar of each conflicting timestamp
+    df.unique_stable(Some(&["datetime".to_string()]), UniqueKeepStrategy::First, None)
+        .map_err(|e| format!("Failed to remove conflicting bars: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::fill::FillPolicy;
+
+    fn source_time(datetime: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn utc(datetime: &str) -> DateTime<Utc> {
+        source_time(datetime).and_utc()
+    }
+
+    // One bar per datetime, the open being its position
+    fn bars(datetimes: &[&str]) -> DataFrame {
+        let micros = datetimes.iter().map(|datetime| source_time(datetime).and_utc().timestamp_micros()).collect::<Vec<i64>>();
+        let datetime = Column::new("datetime".into(), micros).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap();
+        let open = Column::new("open".into(), (0..datetimes.len()).map(|i| i as f64).collect::<Vec<f64>>());
+
+        DataFrame::new(vec![datetime, open]).unwrap()
+    }
+
+    fn datetimes(df: &DataFrame) -> Vec<DateTime<Utc>> {
+        df.column("datetime")
+            .unwrap()
+            .datetime()
+            .unwrap()
+            .into_no_null_iter()
+            .map(|micros| DateTime::from_timestamp_micros(micros).unwrap())
+            .collect()
+    }
+
+    // Flat bars at the given closes
+    fn flat_bars(datetimes: &[&str], closes: &[f64]) -> DataFrame {
+        let micros = datetimes.iter().map(|datetime| utc(datetime).timestamp_micros()).collect::<Vec<i64>>();
+        let price = |name: &str| Column::new(name.into(), closes.to_vec());
+
+        DataFrame::new(vec![
+            Column::new("datetime".into(), micros).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap(),
+            price("open"),
+            price("high"),
+            price("low"),
+            price("close"),
+            Column::new("volume".into(), vec![0i64; datetimes.len()]),
         ])
-        .collect().unwrap();
+        .unwrap()
+    }
 
-        // Merge the DataFrame with the main one
-        main_df = main_df.vstack(&df).unwrap();
+    fn closes(df: &DataFrame) -> Vec<f64> {
+        df.column("close").unwrap().f64().unwrap().into_no_null_iter().collect()
+    }
 
-        // Remove the downloaded file
-        remove_dir_all(&file.strip_suffix(".zip").unwrap()).map_err(|_| "Failed to remove directory")?;
+    #[test]
+    fn the_last_hours_of_a_year_file_move_to_the_next_year() {
+        let df = normalize_timezone(bars(&["2019-12-31 18:59", "2019-12-31 19:00", "2019-12-31 23:59"])).unwrap();
 
-        // Notify that we are done with the parsing
-        tx.send(*progress_weight).await.map_err(|_| "Failed to send progress")?;
+        assert_eq!(df.column("datetime").unwrap().dtype(), &DataType::Datetime(TimeUnit::Microseconds, None));
+        assert_eq!(datetimes(&df), vec![utc("2019-12-31 23:59"), utc("2020-01-01 00:00"), utc("2020-01-01 04:59")]);
     }
 
-    // Close the driver and the server
-    driver.quit().await.map_err(|_| "Failed to quit driver")?;
-    close_driver(port).await.map_err(|_| "Failed to close browser")?;
+    #[test]
+    fn the_first_hours_of_a_month_come_from_the_previous_month() {
+        let df = normalize_timezone(bars(&["2020-02-29 19:00", "2020-03-01 00:00"])).unwrap();
 
-    Ok(main_df)
-}
+        assert_eq!(datetimes(&df), vec![utc("2020-03-01 00:00"), utc("2020-03-01 05:00")]);
+    }
 
-pub fn save_data(df: &mut DataFrame, data_dir: &PathBuf, pair: &str, data_type: &str) -> Result<(), String> {
-    // Get the paths
-    let file_path = format!("{}/{}.{}", data_dir.display(), pair, data_type);
-    let file = File::create(&file_path).map_err(|e| format!("Failed to create file {}: {}", file_path, e))?;
+    #[tokio::test]
+    async fn a_utc_year_needs_the_previous_year_file() {
+        let years = years_to_download("EURUSD", utc("2020-01-01 00:00"), utc("2020-12-31 23:59")).await;
+        assert_eq!(years, vec![2019, 2020]);
 
-    // Save the data with the right format
-    match data_type {
-        "csv" => {
-            CsvWriter::new(file)
-                .finish(df)
-                .map_err(|e| format!("Failed to write CSV file: {}", e))?;
-        }
-        "parquet" => {
-            ParquetWriter::new(file)
-                .finish(df)
-                .map_err(|e| format!("Failed to write Parquet file: {}", e))?;
-        }
-        _ => {
-            return Err(format!("Unsupported data type: {}", data_type));
-        }
+        let years = years_to_download("EURUSD", utc("2020-01-01 05:00"), utc("2020-12-31 23:59")).await;
+        assert_eq!(years, vec![2020]);
     }
-    
-    Ok(())
-}
\ No newline at end of file
+
+    #[test]
+    fn the_range_keeps_the_bars_at_the_year_edges() {
+        let df = normalize_timezone(bars(&["2019-12-31 18:59", "2019-12-31 19:00", "2020-12-31 18:59", "2020-12-31 19:00"])).unwrap();
+        let df = filter_range(df, utc("2020-01-01 00:00"), utc("2020-12-31 23:59"), RangeEnd::Inclusive).unwrap();
+
+        assert_eq!(datetimes(&df), vec![utc("2020-01-01 00:00"), utc("2020-12-31 23:59")]);
+    }
+
+    #[test]
+    fn the_range_end_is_inclusive_or_exclusive() {
+        let df = bars(&["2020-02-29 23:59", "2020-03-01 00:00", "2020-03-01 00:01"]);
+        let (from_date, to_date) = (utc("2020-02-01 00:00"), utc("2020-03-01 00:00"));
+
+        let inclusive = filter_range(df.clone(), from_date, to_date, RangeEnd::Inclusive).unwrap();
+        assert_eq!(datetimes(&inclusive), vec![utc("2020-02-29 23:59"), utc("2020-03-01 00:00")]);
+
+        let exclusive = filter_range(df.clone(), from_date, to_date, RangeEnd::Exclusive).unwrap();
+        assert_eq!(datetimes(&exclusive), vec![utc("2020-02-29 23:59")]);
+
+        // The start is always inclusive
+        let from_start = filter_range(df, utc("2020-03-01 00:00"), utc("2020-03-01 00:01"), RangeEnd::Exclusive).unwrap();
+        assert_eq!(datetimes(&from_start), vec![utc("2020-03-01 00:00")]);
+    }
+
+    #[test]
+    fn a_bar_repeated_at_the_year_boundary_is_kept_once() {
+        let options = DownloadOptions::default();
+        let (mut carry, mut summary) = (Carry::default(), DownloadSummary::default());
+
+        // The last day is held back until the next year is loaded
+        let first = flat_bars(&["2019-12-31 23:00", "2020-01-01 04:58", "2020-01-01 04:59"], &[1.1, 1.2, 1.3]);
+        let first = process_chunk(first, false, &mut carry, &options, &mut summary).unwrap();
+        assert_eq!(datetimes(&first), vec![utc("2019-12-31 23:00")]);
+
+        // The next file starts with the same bar, then a conflicting one
+        let second = flat_bars(&["2020-01-01 04:59", "2020-01-01 04:59", "2020-01-01 05:00"], &[1.3, 1.35, 1.4]);
+        let second = process_chunk(second, true, &mut carry, &options, &mut summary).unwrap();
+        assert_eq!(datetimes(&second), vec![utc("2020-01-01 04:58"), utc("2020-01-01 04:59"), utc("2020-01-01 05:00")]);
+        assert_eq!(closes(&second), vec![1.2, 1.3, 1.4]);
+
+        assert_eq!(summary.duplicates, 1);
+        assert_eq!(summary.conflicts, vec![source_time("2020-01-01 04:59")]);
+        assert_eq!(summary.rows, 4);
+    }
+
+    #[test]
+    fn the_forward_fill_starts_from_the_previous_chunk() {
+        let options = DownloadOptions { fill: FillPolicy::ForwardFill, ..Default::default() };
+        let (mut carry, mut summary) = (Carry::default(), DownloadSummary::default());
+
+        let first = flat_bars(&["2020-03-02 23:58", "2020-03-02 23:59", "2020-03-03 00:01"], &[1.1, 1.2, 1.3]);
+        let first = process_chunk(first, false, &mut carry, &options, &mut summary).unwrap();
+        assert_eq!(datetimes(&first), vec![utc("2020-03-02 23:58"), utc("2020-03-02 23:59")]);
+
+        // 00:00 is missing between the two chunks, it is filled from the close of 23:59
+        let second = flat_bars(&["2020-03-03 00:03"], &[1.4]);
+        let second = process_chunk(second, true, &mut carry, &options, &mut summary).unwrap();
+        assert_eq!(
+            datetimes(&second),
+            vec![utc("2020-03-03 00:00"), utc("2020-03-03 00:01"), utc("2020-03-03 00:02"), utc("2020-03-03 00:03")],
+        );
+        assert_eq!(closes(&second), vec![1.2, 1.3, 1.3, 1.4]);
+        assert_eq!(summary.filled_bars, 2);
+        assert_eq!(summary.rows, 6);
+    }
+
+    #[test]
+    fn the_held_back_day_is_flushed_when_the_last_year_is_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = DownloadOptions::default();
+        let range = (utc("2019-01-01 00:00"), utc("2020-12-31 23:59"));
+        let mut pipeline = YearPipeline::new("EURUSD", &[2019, 2020], range, dir.path(), &options);
+
+        let mut df = flat_bars(&["2019-12-30 10:00", "2019-12-31 10:00", "2019-12-31 11:00"], &[1.1, 1.2, 1.3]);
+        let path = stage(&mut df, &dir.path().join("2019.parquet")).unwrap();
+        let ready = pipeline.push(StagedYear { year: 2019, path, gaps: Vec::new() }).unwrap();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(datetimes(&ready[0].1), vec![utc("2019-12-30 10:00")]);
+
+        // 2020 never came, the last day of 2019 is released at the end
+        let ready = pipeline.finish().unwrap();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].0, 2019);
+        assert_eq!(datetimes(&ready[0].1), vec![utc("2019-12-31 10:00"), utc("2019-12-31 11:00")]);
+        assert_eq!(pipeline.into_summary().0.rows, 3);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::data::{
+    csv::{CsvDatetimeFormat, CsvOptions, write_csv},
+    pairs::price_digits,
+    volume::with_volume,
+};
+
+use polars::prelude::*;
+use std::io::Write;
+
+// Layout of the MT4 history files, build 509 and later
+const HST_VERSION: i32 = 401;
+const HST_COPYRIGHT: &str = "(C)opyright 2003, MetaQuotes Software Corp.";
+// Timeframe of the bars in minutes
+const HST_PERIOD: i32 = 1;
+
+// MT4 names its history files after the symbol and the timeframe, e.g. `EURUSD1.hst`
+pub fn hst_file_name(pair: &str) -> String {
+    format!("{}{}.hst", pair, HST_PERIOD)
+}
+
+// Write the bars as an MT4 history file
+// The header is 148 bytes, followed by one 60 bytes record per bar, all little endian
+// Bars without prices cannot be stored and are left out
+pub fn write_hst<W: Write>(df: &DataFrame, mut writer: W, pair: &str) -> Result<(), String> {
+    write_hst_header(&mut writer, pair)?;
+    write_hst_bars(df, &mut writer, pair)?;
+
+    writer.flush().map_err(|e| format!("Failed to write MT4 history file: {}", e))
+}
+
+pub fn write_hst_header<W: Write>(mut writer: W, pair: &str) -> Result<(), String> {
+    let digits = price_digits(pair);
+
+    let mut header = Vec::with_capacity(148);
+    header.extend_from_slice(&HST_VERSION.to_le_bytes());
+    header.extend_from_slice(&fixed_bytes(HST_COPYRIGHT, 64));
+    header.extend_from_slice(&fixed_bytes(pair, 12));
+    header.extend_from_slice(&HST_PERIOD.to_le_bytes());
+    header.extend_from_slice(&(digits as i32).to_le_bytes());
+    // Time sign and last synchronization, then 13 unused integers
+    header.extend_from_slice(&[0; 4 + 4 + 13 * 4]);
+    writer.write_all(&header).map_err(|e| format!("Failed to write MT4 history file: {}", e))
+}
+
+// Append the records of the bars, after the header
+pub fn write_hst_bars<W: Write>(df: &DataFrame, mut writer: W, pair: &str) -> Result<(), String> {
+    let write_error = |e: std::io::Error| format!("Failed to write MT4 history file: {}", e);
+    let scale = 10f64.powi(price_digits(pair) as i32);
+    let bars = metatrader_bars(df)?;
+
+    let column = |name: &str| bars.column(name).map_err(|e| format!("Missing column {}: {}", name, e));
+    let times = column("datetime")?.i64().map_err(|e| e.to_string())?.clone();
+    let volumes = column("volume")?.i64().map_err(|e| e.to_string())?.clone();
+    let prices = ["open", "high", "low", "close"].iter()
+        .map(|name| Ok(column(name)?.f64().map_err(|e| e.to_string())?.clone()))
+        .collect::<Result<Vec<Float64Chunked>, String>>()?;
+
+    for i in 0..bars.height() {
+        let mut record = Vec::with_capacity(60);
+        record.extend_from_slice(&times.get(i).unwrap_or_default().to_le_bytes());
+        // Rounded to the instrument's digits so MT4 shows the quoted prices
+        for price in &prices {
+            let price = (price.get(i).unwrap_or_default() * scale).round() / scale;
+            record.extend_from_slice(&price.to_le_bytes());
+        }
+        // Tick volume, spread and real volume
+        record.extend_from_slice(&volumes.get(i).unwrap_or_default().to_le_bytes());
+        record.extend_from_slice(&0i32.to_le_bytes());
+        record.extend_from_slice(&0i64.to_le_bytes());
+        writer.write_all(&record).map_err(write_error)?;
+    }
+
+    Ok(())
+}
+
+// Write the bars in the layout imported by MT5 for custom symbols
+// Tab separated `<DATE> <TIME> <OPEN> <HIGH> <LOW> <CLOSE> <TICKVOL> <VOL> <SPREAD>`
+// The header is left out when appending to a file
+pub fn write_mt5_csv<W: Write>(df: &DataFrame, writer: W, pair: &str, header: bool) -> Result<(), String> {
+    let digits = price_digits(pair);
+    let bars = with_volume(df)?.lazy()
+        .filter(col("close").is_not_null())
+        .with_columns([lit(0i64).alias("real_volume"), lit(0i64).alias("spread")])
+        .collect()
+        .map_err(|e| format!("Failed to prepare MT5 bars: {}", e))?;
+
+    let options = CsvOptions {
+        delimiter: b'\t',
+        header,
+        datetime_format: CsvDatetimeFormat::SplitDateTime {
+            date_format: "%Y.%m.%d".to_string(),
+            time_format: "%H:%M:%S".to_string(),
+        },
+        float_precision: Some(digits as usize),
+        ..Default::default()
+    }
+    .with_columns(&[
+        ("date", "<DATE>"),
+        ("time", "<TIME>"),
+        ("open", "<OPEN>"),
+        ("high", "<HIGH>"),
+        ("low", "<LOW>"),
+        ("close", "<CLOSE>"),
+        ("volume", "<TICKVOL>"),
+        ("real_volume", "<VOL>"),
+        ("spread", "<SPREAD>"),
+    ]);
+
+    write_csv(&bars, writer, &options)
+}
+
+// Unix seconds, Float64 prices and Int64 volumes
+// A dropped volume column is written as zeros
+fn metatrader_bars(df: &DataFrame) -> Result<DataFrame, String> {
+    let mut bars = with_volume(df)?.lazy()
+        .filter(col("close").is_not_null())
+        .select([
+            (col("datetime").dt().timestamp(TimeUnit::Milliseconds) / lit(1000i64)).cast(DataType::Int64).alias("datetime"),
+            col("open").cast(DataType::Float64),
+            col("high").cast(DataType::Float64),
+            col("low").cast(DataType::Float64),
+            col("close").cast(DataType::Float64),
+            col("volume").cast(DataType::Int64).fill_null(lit(0i64)),
+        ])
+        .collect()
+        .map_err(|e| format!("Failed to prepare MetaTrader bars: {}", e))?;
+    bars.as_single_chunk_par();
+
+    Ok(bars)
+}
+
+// Null padded, truncated to leave room for the terminating null
+fn fixed_bytes(text: &str, size: usize) -> Vec<u8> {
+    let mut bytes = text.as_bytes()[..text.len().min(size - 1)].to_vec();
+    bytes.resize(size, 0);
+
+    bytes
+}
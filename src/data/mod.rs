@@ -1,2 +1,25 @@
+pub mod archive;
+pub mod backtesting;
+pub mod bars;
+pub mod calendar;
+pub mod csv;
+pub mod dataset;
+pub mod fill;
+pub mod gaps;
 pub mod handler;
-pub mod pairs;
\ No newline at end of file
+pub mod metatrader;
+pub mod naming;
+pub mod options;
+pub mod output;
+pub mod pairs;
+pub mod prices;
+pub mod reader;
+pub mod report;
+pub mod request;
+pub mod sessions;
+pub mod sqlite;
+pub mod staging;
+pub mod stream;
+pub mod summary;
+pub mod validation;
+pub mod volume;
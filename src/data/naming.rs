@@ -0,0 +1,269 @@
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use polars::prelude::*;
+use std::{fmt, str::FromStr};
+
+// Used when no template is given
+pub const DEFAULT_FILE_NAME: &str = "{symbol}.{ext}";
+pub const DEFAULT_SPLIT_FILE_NAME: &str = "{symbol}_{period}.{ext}";
+
+// Placeholders that can be used in a file name template
+const PLACEHOLDERS: [&str; 6] = ["symbol", "timeframe", "from", "to", "period", "ext"];
+
+// Duration of the bars
+// HistData only publishes one minute bars
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Timeframe {
+    #[default]
+    M1,
+}
+
+impl fmt::Display for Timeframe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timeframe::M1 => write!(f, "M1"),
+        }
+    }
+}
+
+impl FromStr for Timeframe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "M1" | "1M" | "1MIN" => Ok(Timeframe::M1),
+            _ => Err(format!("Unknown timeframe '{}', HistData only has M1 bars", s.trim())),
+        }
+    }
+}
+
+// Write one file per period instead of a single file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SplitPeriod {
+    #[default]
+    None,
+    Year,
+    Month,
+    // ISO weeks, starting on Monday
+    Week,
+}
+
+impl SplitPeriod {
+    // First second of the period holding `datetime`
+    fn start_of(&self, datetime: NaiveDateTime) -> NaiveDateTime {
+        let date = datetime.date();
+        let start = match self {
+            SplitPeriod::None => return datetime,
+            SplitPeriod::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+            SplitPeriod::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap(),
+            SplitPeriod::Week => date - Days::new(date.weekday().num_days_from_monday() as u64),
+        };
+
+        start.and_hms_opt(0, 0, 0).unwrap()
+    }
+
+    // Last second of the period starting at `start`
+    fn end_of(&self, start: NaiveDateTime) -> NaiveDateTime {
+        let next = match self {
+            SplitPeriod::None => return start,
+            SplitPeriod::Year => start.checked_add_months(Months::new(12)).unwrap(),
+            SplitPeriod::Month => start.checked_add_months(Months::new(1)).unwrap(),
+            SplitPeriod::Week => start + Days::new(7),
+        };
+
+        next - TimeDelta::seconds(1)
+    }
+
+    // `2020`, `2020-03` or `2020-W05`
+    fn label(&self, start: NaiveDateTime) -> String {
+        match self {
+            SplitPeriod::None => String::new(),
+            SplitPeriod::Year => start.format("%Y").to_string(),
+            SplitPeriod::Month => start.format("%Y-%m").to_string(),
+            SplitPeriod::Week => start.format("%G-W%V").to_string(),
+        }
+    }
+}
+
+// A slice of the bars and the values of the placeholders describing it
+#[derive(Clone, Debug)]
+pub struct OutputPart {
+    pub from_date: DateTime<Utc>,
+    pub to_date: DateTime<Utc>,
+    // Empty when the output is not split
+    pub period: String,
+    pub df: DataFrame,
+}
+
+// Split the bars in runs of the same period
+// The bars are sorted so every period is a single slice
+// The range of each part is the requested range clipped to its period
+pub fn split_by_period(df: &DataFrame, split: SplitPeriod, from_date: DateTime<Utc>, to_date: DateTime<Utc>) -> Result<Vec<OutputPart>, String> {
+    if split == SplitPeriod::None {
+        return Ok(vec![OutputPart { from_date, to_date, period: String::new(), df: df.clone() }]);
+    }
+
+    let datetimes = df.column("datetime")
+        .and_then(|c| c.cast(&DataType::Datetime(TimeUnit::Microseconds, None)))
+        .map_err(|e| format!("Failed to read datetimes: {}", e))?;
+    let starts = datetimes.datetime()
+        .map_err(|e| format!("Failed to read datetimes: {}", e))?
+        .into_no_null_iter()
+        .map(|micros| split.start_of(DateTime::from_timestamp_micros(micros).unwrap().naive_utc()))
+        .collect::<Vec<NaiveDateTime>>();
+
+    let mut parts = Vec::new();
+    let mut first = 0;
+    for i in 1..=starts.len() {
+        if i == starts.len() || starts[i] != starts[first] {
+            let start = starts[first];
+            parts.push(OutputPart {
+                from_date: start.and_utc().max(from_date),
+                to_date: split.end_of(start).and_utc().min(to_date),
+                period: split.label(start),
+                df: df.slice(first as i64, i - first),
+            });
+            first = i;
+        }
+    }
+
+    Ok(parts)
+}
+
+// Fill a file name template such as `{symbol}_{timeframe}_{from}_{to}.{ext}`
+// The dates are written as `YYYYMMDD`
+// Checked beforehand with `check_template`
+pub fn render_file_name(template: &str, pair: &str, timeframe: Timeframe, extension: &str, part: &OutputPart) -> String {
+    template
+        .replace("{symbol}", pair)
+        .replace("{timeframe}", &timeframe.to_string())
+        .replace("{from}", &part.from_date.format("%Y%m%d").to_string())
+        .replace("{to}", &part.to_date.format("%Y%m%d").to_string())
+        .replace("{period}", &part.period)
+        .replace("{ext}", extension)
+}
+
+// Refuse unknown placeholders and split outputs that would all get the same name
+pub fn check_template(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("Unclosed placeholder in file name template '{}'", template));
+        };
+        let name = &rest[start + 1..start + end];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "Unknown placeholder {{{}}} in file name template '{}', expected one of {}",
+                name,
+                template,
+                PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", "),
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    Ok(())
+}
+
+pub fn check_split_template(template: &str, split: SplitPeriod) -> Result<(), String> {
+    check_template(template)?;
+
+    if split != SplitPeriod::None && !["{period}", "{from}", "{to}"].iter().any(|p| template.contains(p)) {
+        return Err(format!("The file name template '{}' needs {{period}}, {{from}} or {{to}} to split the output", template));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(datetime: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S").unwrap().and_utc()
+    }
+
+    fn bars(datetimes: &[&str]) -> DataFrame {
+        let micros = datetimes.iter().map(|datetime| utc(datetime).timestamp_micros()).collect::<Vec<i64>>();
+
+        DataFrame::new(vec![Column::new("datetime".into(), micros).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap()]).unwrap()
+    }
+
+    // The period, range and number of bars of each part
+    fn split(datetimes: &[&str], split: SplitPeriod, from_date: &str, to_date: &str) -> Vec<(String, DateTime<Utc>, DateTime<Utc>, usize)> {
+        split_by_period(&bars(datetimes), split, utc(from_date), utc(to_date))
+            .unwrap()
+            .into_iter()
+            .map(|part| (part.period, part.from_date, part.to_date, part.df.height()))
+            .collect()
+    }
+
+    #[test]
+    fn the_months_are_clipped_to_the_range() {
+        let parts = split(
+            &["2020-01-15 00:00:00", "2020-01-31 23:59:00", "2020-02-01 00:00:00"],
+            SplitPeriod::Month,
+            "2020-01-15 00:00:00",
+            "2020-02-10 00:00:00",
+        );
+
+        assert_eq!(parts, [
+            ("2020-01".to_string(), utc("2020-01-15 00:00:00"), utc("2020-01-31 23:59:59"), 2),
+            ("2020-02".to_string(), utc("2020-02-01 00:00:00"), utc("2020-02-10 00:00:00"), 1),
+        ]);
+    }
+
+    #[test]
+    fn the_iso_weeks_cross_the_year_end() {
+        // 2020-12-31 and 2021-01-03 are both in the 53rd week of 2020
+        let parts = split(
+            &["2020-12-27 23:59:00", "2020-12-28 00:00:00", "2021-01-03 23:59:00", "2021-01-04 00:00:00"],
+            SplitPeriod::Week,
+            "2020-12-01 00:00:00",
+            "2021-01-31 00:00:00",
+        );
+
+        assert_eq!(parts, [
+            ("2020-W52".to_string(), utc("2020-12-21 00:00:00"), utc("2020-12-27 23:59:59"), 1),
+            ("2020-W53".to_string(), utc("2020-12-28 00:00:00"), utc("2021-01-03 23:59:59"), 2),
+            ("2021-W01".to_string(), utc("2021-01-04 00:00:00"), utc("2021-01-10 23:59:59"), 1),
+        ]);
+    }
+
+    #[test]
+    fn an_output_that_is_not_split_is_a_single_part() {
+        let parts = split(&["2019-06-01 00:00:00", "2021-06-01 00:00:00"], SplitPeriod::None, "2019-01-01 00:00:00", "2021-12-31 00:00:00");
+        assert_eq!(parts, [(String::new(), utc("2019-01-01 00:00:00"), utc("2021-12-31 00:00:00"), 2)]);
+
+        let parts = split(&["2019-06-01 00:00:00", "2021-06-01 00:00:00"], SplitPeriod::Year, "2019-01-01 00:00:00", "2021-12-31 00:00:00");
+        assert_eq!(parts.iter().map(|part| part.0.as_str()).collect::<Vec<&str>>(), ["2019", "2021"]);
+    }
+
+    #[test]
+    fn the_placeholders_are_filled() {
+        let part = OutputPart {
+            from_date: utc("2020-01-15 00:00:00"),
+            to_date: utc("2020-01-31 23:59:59"),
+            period: "2020-01".to_string(),
+            df: bars(&[]),
+        };
+
+        assert_eq!(
+            render_file_name("{symbol}_{timeframe}_{from}_{to}_{period}.{ext}", "EURUSD", Timeframe::M1, "csv.gz", &part),
+            "EURUSD_M1_20200115_20200131_2020-01.csv.gz",
+        );
+        assert_eq!("1min".parse::<Timeframe>(), Ok(Timeframe::M1));
+        assert!("H1".parse::<Timeframe>().is_err());
+    }
+
+    #[test]
+    fn the_templates_are_checked() {
+        assert!(check_template("{symbol}_{timeframe}.{ext}").is_ok());
+        assert!(check_template("{symbol}_{year}.{ext}").unwrap_err().starts_with("Unknown placeholder {year}"));
+        assert!(check_template("{symbol.{ext}").unwrap_err().starts_with("Unknown placeholder {symbol.{ext}"));
+        assert!(check_template("{symbol}_{ext").unwrap_err().starts_with("Unclosed placeholder"));
+
+        assert!(check_split_template("{symbol}.{ext}", SplitPeriod::None).is_ok());
+        assert!(check_split_template("{symbol}.{ext}", SplitPeriod::Month).is_err());
+        assert!(check_split_template("{symbol}_{from}.{ext}", SplitPeriod::Month).is_ok());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::data::{calendar::MarketCalendar, fill::FillPolicy, output::OutputOptions, reader::ReadOptions, sessions::TradingSession, validation::ValidationOptions, volume::VolumeOptions};
+
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+// How the end of the requested range is treated
+// The start of the range is always inclusive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RangeEnd {
+    // A bar stamped exactly at the end is dropped
+    Exclusive,
+    // A bar stamped exactly at the end is kept
+    #[default]
+    Inclusive,
+}
+
+// Which trading sessions are kept or tagged
+#[derive(Clone, Debug)]
+pub struct SessionOptions {
+    // Only keep the bars inside one of these sessions
+    // Every bar is kept when empty
+    pub filter: Vec<TradingSession>,
+    // Add a `session` column naming the sessions of each bar
+    pub tag: bool,
+    pub tag_sessions: Vec<TradingSession>,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        SessionOptions {
+            filter: Vec::new(),
+            tag: false,
+            tag_sessions: TradingSession::main_sessions(),
+        }
+    }
+}
+
+// How the archives are downloaded from HistData
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    // A headless Chrome driven through chromedriver
+    // Each worker starts its own chromedriver on a free port of the range
+    ChromeDriver { ports: (usize, usize) },
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::ChromeDriver { ports: (9000, 9500) }
+    }
+}
+
+// Shared flag asking the workers to stop
+// They finish the year in progress, then close their browser
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    // Cancelling the parent also cancels this token
+    parent: Option<Arc<CancelToken>>,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.parent.as_ref().is_some_and(|parent| parent.is_cancelled())
+    }
+
+    // A token cancelled along with this one, which can also be cancelled alone
+    pub fn child(&self) -> CancelToken {
+        CancelToken {
+            cancelled: Arc::default(),
+            parent: Some(Arc::new(self.clone())),
+        }
+    }
+}
+
+// Everything that changes how the downloaded data is processed
+// The default keeps the behaviour of the interactive program
+#[derive(Clone, Debug, Default)]
+pub struct DownloadOptions {
+    pub range_end: RangeEnd,
+    // Archive limits used to read the downloads
+    pub read: ReadOptions,
+    pub validation: ValidationOptions,
+    pub calendar: MarketCalendar,
+    // Drop the stray bars stamped while the market is closed
+    pub drop_closed_bars: bool,
+    // Reindex to a complete minute grid within market hours
+    pub fill: FillPolicy,
+    pub sessions: SessionOptions,
+    // Keep, drop or replace the volume column
+    pub volume: VolumeOptions,
+    pub output: OutputOptions,
+    // Number of workers downloading years in parallel, 5 when `None`
+    pub concurrency: Option<usize>,
+    pub backend: Backend,
+    // Keep the downloaded archives in this directory
+    // and read them from there instead of downloading them again
+    pub cache_dir: Option<PathBuf>,
+    pub cancel: CancelToken,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_child_token_follows_its_parent() {
+        let parent = CancelToken::default();
+        let child = parent.child();
+
+        let other = parent.child();
+        other.cancel();
+        assert!(!parent.is_cancelled() && !child.is_cancelled());
+
+        parent.cancel();
+        assert!(child.is_cancelled());
+    }
+}
@@ -0,0 +1,1125 @@
+use crate::data::{
+    backtesting::{BacktestPreset, BacktestWriter, backtest_path},
+    csv::{CsvOptions, write_csv},
+    dataset::write_partitioned,
+    metatrader::{hst_file_name, write_hst_bars, write_hst_header, write_mt5_csv},
+    naming::{DEFAULT_FILE_NAME, DEFAULT_SPLIT_FILE_NAME, SplitPeriod, Timeframe, check_split_template, render_file_name, split_by_period},
+    prices::{PriceStorage, compact_prices, convert_prices, price_metadata, price_scale, recorded_scale, restore_prices, stored_as_points},
+    sqlite::{SqliteOptions, write_sqlite},
+    staging::read_staged,
+};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use flate2::{Compression, write::GzEncoder};
+use polars::{io::ipc::BatchedWriter as IpcBatchedWriter, prelude::*};
+use polars_parquet::{
+    arrow::write::{
+        BrotliLevel, CompressionOptions, Encoding, FileWriter, GzipLevel, RowGroupIterator, StatisticsOptions, Version, WriteOptions, ZstdLevel, transverse,
+    },
+    parquet::{error::ParquetError, metadata::KeyValue},
+};
+use std::{
+    fmt,
+    fs::{File, create_dir_all, remove_file, rename},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+// Every format the data can be saved in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    CsvGzip,
+    CsvZstd,
+    Parquet,
+    // Hive-partitioned Parquet dataset, one file per month
+    ParquetDataset,
+    // Arrow IPC file, also known as Feather v2
+    ArrowIpc,
+    // Newline-delimited JSON, one bar per line
+    NdJson,
+    // A SQLite database shared by every instrument
+    Sqlite,
+    // MT4 history file, named after the symbol and the timeframe
+    Mt4History,
+    // Tab separated bars imported by MT5 for custom symbols
+    Mt5Csv,
+    // Layout expected by a backtesting framework
+    Backtest(BacktestPreset),
+}
+
+// What to do when an output file already exists
+// SQLite databases and datasets always merge, the backtesting presets cannot be merged
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    // Refuse to touch the existing file
+    #[default]
+    ErrorIfExists,
+    Overwrite,
+    // Combine the existing bars with the new ones, the new bar wins on the same timestamp
+    Merge,
+}
+
+// How the output is written
+// The file name template, the split and the write mode only apply to the formats written as plain files
+#[derive(Clone, Debug, Default)]
+pub struct OutputOptions {
+    pub write_mode: WriteMode,
+    // Template such as `{symbol}_{timeframe}_{from}_{to}.{ext}`, see `naming`
+    // `<PAIR>.<ext>` when `None`, or `<PAIR>_<period>.<ext>` when split
+    pub file_name: Option<String>,
+    pub split: SplitPeriod,
+    // Written in the `{timeframe}` placeholder
+    pub timeframe: Timeframe,
+    pub csv: CsvOptions,
+    pub parquet: ParquetOptions,
+    pub sqlite: SqliteOptions,
+    // Type of the price columns, with their scale in the metadata
+    pub prices: PriceStorage,
+    // Write the datetimes as the wall clock time of this timezone, UTC when `None`
+    // Refused for the outputs that are merged, MetaTrader 4 and the backtesting presets
+    pub timezone: Option<Tz>,
+}
+
+impl OutputFormat {
+    pub fn all() -> Vec<OutputFormat> {
+        vec![
+            OutputFormat::Csv,
+            OutputFormat::CsvGzip,
+            OutputFormat::CsvZstd,
+            OutputFormat::Parquet,
+            OutputFormat::ParquetDataset,
+            OutputFormat::ArrowIpc,
+            OutputFormat::NdJson,
+            OutputFormat::Sqlite,
+            OutputFormat::Mt4History,
+            OutputFormat::Mt5Csv,
+        ]
+        .into_iter()
+        .chain(BacktestPreset::all().into_iter().map(OutputFormat::Backtest))
+        .collect()
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::CsvGzip => "csv.gz",
+            OutputFormat::CsvZstd => "csv.zst",
+            OutputFormat::Parquet | OutputFormat::ParquetDataset => "parquet",
+            OutputFormat::ArrowIpc => "arrow",
+            OutputFormat::NdJson => "ndjson",
+            OutputFormat::Sqlite => "sqlite",
+            OutputFormat::Mt4History => "hst",
+            OutputFormat::Mt5Csv => "mt5.csv",
+            OutputFormat::Backtest(preset) => preset.extension(),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::CsvGzip => "csv (gzip)",
+            OutputFormat::CsvZstd => "csv (zstd)",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::ParquetDataset => "parquet dataset (partitioned by month)",
+            OutputFormat::ArrowIpc => "arrow ipc / feather",
+            OutputFormat::NdJson => "ndjson",
+            OutputFormat::Sqlite => "sqlite",
+            OutputFormat::Mt4History => "metatrader 4 history (.hst)",
+            OutputFormat::Mt5Csv => "metatrader 5 csv",
+            OutputFormat::Backtest(preset) => return write!(f, "{} backtest", preset),
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    // Accept the extensions and a few common names
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "csv.gz" | "gzip" | "gz" => Ok(OutputFormat::CsvGzip),
+            "csv.zst" | "zstd" | "zst" => Ok(OutputFormat::CsvZstd),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "dataset" | "hive" => Ok(OutputFormat::ParquetDataset),
+            "arrow" | "ipc" | "feather" => Ok(OutputFormat::ArrowIpc),
+            "ndjson" | "jsonl" => Ok(OutputFormat::NdJson),
+            "sqlite" | "sqlite3" | "db" => Ok(OutputFormat::Sqlite),
+            "hst" | "mt4" => Ok(OutputFormat::Mt4History),
+            "mt5.csv" | "mt5" => Ok(OutputFormat::Mt5Csv),
+            "backtrader" => Ok(OutputFormat::Backtest(BacktestPreset::Backtrader)),
+            "ninjatrader" | "ninja" => Ok(OutputFormat::Backtest(BacktestPreset::NinjaTrader)),
+            "lean" | "quantconnect" => Ok(OutputFormat::Backtest(BacktestPreset::Lean)),
+            "zipline" => Ok(OutputFormat::Backtest(BacktestPreset::Zipline)),
+            other => Err(format!("Unsupported data type: {}", other)),
+        }
+    }
+}
+
+// Compression codecs available for Parquet
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParquetCodec {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Lz4,
+    #[default]
+    Zstd,
+    Brotli,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParquetOptions {
+    pub codec: ParquetCodec,
+    // Codec level, the codec default when `None`
+    // Only used by gzip, zstd and brotli
+    pub level: Option<u32>,
+    pub row_group_size: usize,
+    // Write min, max and null count of each column chunk
+    pub statistics: bool,
+    // Dictionary encode the integer and string columns
+    pub dictionary: bool,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        ParquetOptions {
+            codec: ParquetCodec::default(),
+            level: None,
+            row_group_size: 512 * 512,
+            statistics: true,
+            dictionary: true,
+        }
+    }
+}
+
+impl ParquetOptions {
+    fn compression(&self) -> Result<CompressionOptions, String> {
+        Ok(match self.codec {
+            ParquetCodec::Uncompressed => CompressionOptions::Uncompressed,
+            ParquetCodec::Snappy => CompressionOptions::Snappy,
+            ParquetCodec::Lz4 => CompressionOptions::Lz4Raw,
+            ParquetCodec::Gzip => CompressionOptions::Gzip(codec_level(self.level, GzipLevel::try_new)?),
+            ParquetCodec::Zstd => CompressionOptions::Zstd(codec_level(self.level, ZstdLevel::try_new)?),
+            ParquetCodec::Brotli => CompressionOptions::Brotli(codec_level(self.level, BrotliLevel::try_new)?),
+        })
+    }
+}
+
+// Check the level against the type and the range of the codec
+fn codec_level<T: TryFrom<u32>, L>(level: Option<u32>, try_new: impl Fn(T) -> Result<L, ParquetError>) -> Result<Option<L>, String> {
+    let level_error = |level: u32| format!("Invalid compression level: {}", level);
+
+    level.map(|level| {
+        let value = T::try_from(level).map_err(|_| level_error(level))?;
+        try_new(value).map_err(|e| format!("{}: {}", level_error(level), e))
+    })
+    .transpose()
+}
+
+// Parquet file written one chunk of bars at a time
+// Each chunk is cut in row groups of `row_group_size` rows
+pub struct ParquetSink<W: Write> {
+    writer: FileWriter<W>,
+    schema: ArrowSchema,
+    write_options: WriteOptions,
+    encodings: Vec<Vec<Encoding>>,
+    row_group_size: usize,
+}
+
+impl<W: Write> ParquetSink<W> {
+    pub fn try_new(writer: W, schema: &Schema, options: &ParquetOptions) -> Result<Self, String> {
+        let schema = schema.to_arrow(CompatLevel::newest());
+        let write_options = WriteOptions {
+            statistics: if options.statistics { StatisticsOptions::default() } else { StatisticsOptions::empty() },
+            version: Version::V1,
+            compression: options.compression()?,
+            data_page_size: None,
+        };
+        let encodings = if options.dictionary {
+            get_encodings(&schema)
+        } else {
+            schema.iter_values().map(|field| transverse(&field.dtype, |_| Encoding::Plain)).collect()
+        };
+        let writer = FileWriter::try_new(writer, schema.clone(), write_options).map_err(parquet_error)?;
+
+        Ok(ParquetSink {
+            writer,
+            schema,
+            write_options,
+            encodings,
+            row_group_size: options.row_group_size.max(1),
+        })
+    }
+
+    pub fn write(&mut self, df: &DataFrame) -> Result<(), String> {
+        let row_group_size = self.row_group_size;
+        let batches = (0..df.height())
+            .step_by(row_group_size)
+            .flat_map(|offset| {
+                let mut row_group = df.slice(offset as i64, row_group_size);
+                row_group.as_single_chunk();
+                row_group.iter_chunks(CompatLevel::newest(), false).collect::<Vec<_>>()
+            })
+            .map(Ok);
+        let row_groups = RowGroupIterator::try_new(batches, &self.schema, self.write_options, self.encodings.clone()).map_err(parquet_error)?;
+
+        for row_group in row_groups {
+            self.writer.write(row_group.map_err(parquet_error)?).map_err(parquet_error)?;
+        }
+
+        Ok(())
+    }
+
+    // Write the footer with the key-value metadata
+    pub fn finish(mut self, metadata: &[(String, String)]) -> Result<W, String> {
+        let metadata = metadata.iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+            .collect();
+        self.writer.end(Some(metadata)).map_err(parquet_error)?;
+
+        Ok(self.writer.into_inner())
+    }
+}
+
+fn parquet_error(e: PolarsError) -> String {
+    format!("Failed to write Parquet file: {}", e)
+}
+
+// Write a Parquet file with key-value metadata in its footer
+pub fn write_parquet(df: &mut DataFrame, file: File, options: &ParquetOptions, metadata: &[(String, String)]) -> Result<(), String> {
+    let mut sink = ParquetSink::try_new(file, df.schema(), options)?;
+    sink.write(df)?;
+    sink.finish(metadata)?;
+
+    Ok(())
+}
+
+// The open writer of a plain file, created with the schema of the first chunk
+enum FileSink {
+    Csv(BufWriter<File>),
+    CsvGzip(GzEncoder<BufWriter<File>>),
+    CsvZstd(zstd::Encoder<'static, BufWriter<File>>),
+    Parquet(Box<ParquetSink<BufWriter<File>>>),
+    ArrowIpc(IpcBatchedWriter<BufWriter<File>>),
+    NdJson(BufWriter<File>),
+    Mt4History(BufWriter<File>),
+    Mt5Csv(BufWriter<File>),
+}
+
+// Write a plain file one chunk of bars at a time
+// The chunks must be sorted and must not overlap,
+// so the memory used does not depend on the size of the file
+// The lazy sinks of Polars are not used: they only write to a path, so the files
+// could not be compressed, split per period or renamed once complete, `sink_parquet`
+// cannot add the key-value metadata to the footer, and the CSV dialects and
+// MetaTrader layouts are not expressions; one year of bars is in memory at most
+pub struct ChunkWriter<'a> {
+    format: OutputFormat,
+    options: &'a OutputOptions,
+    pair: &'a str,
+    // Until the first chunk gives the schema
+    file: Option<BufWriter<File>>,
+    sink: Option<FileSink>,
+}
+
+impl<'a> ChunkWriter<'a> {
+    // Datasets, SQLite databases and backtesting presets are not plain files
+    pub fn new(file: File, format: OutputFormat, options: &'a OutputOptions, pair: &'a str) -> Result<Self, String> {
+        if matches!(format, OutputFormat::ParquetDataset | OutputFormat::Sqlite | OutputFormat::Backtest(_)) {
+            return Err(format!("The {} output can only be written with save_data", format));
+        }
+
+        Ok(ChunkWriter {
+            format,
+            options,
+            pair,
+            file: Some(BufWriter::new(file)),
+            sink: None,
+        })
+    }
+
+    pub fn write(&mut self, df: &DataFrame) -> Result<(), String> {
+        let compress_error = |e: std::io::Error| format!("Failed to compress CSV file: {}", e);
+        let first = self.sink.is_none();
+
+        if let Some(file) = self.file.take() {
+            self.sink = Some(match self.format {
+                OutputFormat::Csv => FileSink::Csv(file),
+                OutputFormat::CsvGzip => FileSink::CsvGzip(GzEncoder::new(file, Compression::default())),
+                OutputFormat::CsvZstd => FileSink::CsvZstd(zstd::Encoder::new(file, 0).map_err(compress_error)?),
+                OutputFormat::Parquet => FileSink::Parquet(Box::new(ParquetSink::try_new(file, df.schema(), &self.options.parquet)?)),
+                OutputFormat::ArrowIpc => FileSink::ArrowIpc(
+                    IpcWriter::new(file)
+                        .batched(df.schema())
+                        .map_err(|e| format!("Failed to write Arrow IPC file: {}", e))?,
+                ),
+                OutputFormat::NdJson => FileSink::NdJson(file),
+                OutputFormat::Mt4History => {
+                    let mut file = file;
+                    write_hst_header(&mut file, self.pair)?;
+                    FileSink::Mt4History(file)
+                }
+                OutputFormat::Mt5Csv => FileSink::Mt5Csv(file),
+                OutputFormat::ParquetDataset | OutputFormat::Sqlite | OutputFormat::Backtest(_) => unreachable!(),
+            });
+        }
+
+        // The CSV header is only written before the first chunk
+        let csv_options = CsvOptions {
+            header: self.options.csv.header && first,
+            ..self.options.csv.clone()
+        };
+        match self.sink.as_mut().unwrap() {
+            FileSink::Csv(writer) => write_csv(df, writer, &csv_options),
+            FileSink::CsvGzip(encoder) => write_csv(df, encoder, &csv_options),
+            FileSink::CsvZstd(encoder) => write_csv(df, encoder, &csv_options),
+            FileSink::Parquet(sink) => sink.write(df),
+            FileSink::ArrowIpc(writer) => writer.write_batch(df).map_err(|e| format!("Failed to write Arrow IPC file: {}", e)),
+            FileSink::NdJson(writer) => JsonWriter::new(writer)
+                .with_json_format(JsonFormat::JsonLines)
+                .finish(&mut df.clone())
+                .map_err(|e| format!("Failed to write NDJSON file: {}", e)),
+            FileSink::Mt4History(writer) => write_hst_bars(df, writer, self.pair),
+            FileSink::Mt5Csv(writer) => write_mt5_csv(df, writer, self.pair, first),
+        }
+    }
+
+    // Close the file, the metadata is embedded in the formats that support it
+    pub fn finish(self, metadata: &[(String, String)]) -> Result<(), String> {
+        let compress_error = |e: std::io::Error| format!("Failed to compress CSV file: {}", e);
+
+        let mut file = match self.sink {
+            None => self.file.unwrap(),
+            Some(FileSink::Csv(file) | FileSink::NdJson(file) | FileSink::Mt4History(file) | FileSink::Mt5Csv(file)) => file,
+            Some(FileSink::CsvGzip(encoder)) => encoder.finish().map_err(compress_error)?,
+            Some(FileSink::CsvZstd(encoder)) => encoder.finish().map_err(compress_error)?,
+            Some(FileSink::Parquet(sink)) => sink.finish(metadata)?,
+            Some(FileSink::ArrowIpc(mut writer)) => {
+                writer.finish().map_err(|e| format!("Failed to write Arrow IPC file: {}", e))?;
+                return Ok(());
+            }
+        };
+
+        file.flush().map_err(|e| format!("Failed to write file: {}", e))
+    }
+}
+
+// Write the DataFrame to an already created file
+// Datasets and SQLite databases are not plain files and are written by `save_data`
+// The MetaTrader and backtesting formats need the symbol and are also written by `save_data`
+// The metadata is embedded in the formats that support it
+pub fn write_data(df: &mut DataFrame, file: File, format: OutputFormat, options: &OutputOptions, metadata: &[(String, String)]) -> Result<(), String> {
+    if matches!(format, OutputFormat::Mt4History | OutputFormat::Mt5Csv) {
+        return Err(format!("The {} output can only be written with save_data", format));
+    }
+
+    let mut writer = ChunkWriter::new(file, format, options, "")?;
+    writer.write(df)?;
+    writer.finish(metadata)
+}
+
+// Read back a file written by `write_data`
+// The CSV files must use the default dialect
+// The points of a Parquet file are converted back to prices with the scale
+// of its metadata, the other formats do not record it and keep the points
+pub fn read_data(path: &Path, format: OutputFormat) -> Result<DataFrame, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open file {}: {}", path.display(), e))?;
+    let read_error = |e: PolarsError| format!("Failed to read file {}: {}", path.display(), e);
+
+    // The CSV files have no schema, so the datetime column is parsed again
+    let csv_options = || CsvReadOptions::default()
+        .with_has_header(true)
+        .map_parse_options(|options| options.with_try_parse_dates(true));
+
+    match format {
+        OutputFormat::Csv => csv_options().into_reader_with_file_handle(file).finish().map_err(read_error),
+        OutputFormat::CsvGzip => {
+            let decoder = flate2::read::GzDecoder::new(file);
+            csv_options().into_reader_with_file_handle(std::io::Cursor::new(read_all(decoder, path)?)).finish().map_err(read_error)
+        }
+        OutputFormat::CsvZstd => {
+            let decoder = zstd::Decoder::new(file).map_err(|e| format!("Failed to decompress {}: {}", path.display(), e))?;
+            csv_options().into_reader_with_file_handle(std::io::Cursor::new(read_all(decoder, path)?)).finish().map_err(read_error)
+        }
+        OutputFormat::Parquet => read_parquet(file, path),
+        OutputFormat::ParquetDataset => Err(format!("Datasets are read with scan_partitioned: {}", path.display())),
+        OutputFormat::ArrowIpc => IpcReader::new(file).finish().map_err(read_error),
+        OutputFormat::NdJson => {
+            // JSON has no datetime type, the timestamps are written as text
+            JsonReader::new(file)
+                .with_json_format(JsonFormat::JsonLines)
+                .finish()
+                .and_then(|df| {
+                    df.lazy()
+                        .with_column(col("datetime").str().to_datetime(
+                            Some(TimeUnit::Microseconds),
+                            None,
+                            StrptimeOptions::default(),
+                            lit("raise"),
+                        ))
+                        .collect()
+                })
+                .map_err(read_error)
+        }
+        OutputFormat::Sqlite => Err(format!("Reading SQLite databases is not supported: {}", path.display())),
+        OutputFormat::Mt4History | OutputFormat::Mt5Csv => Err(format!("Reading MetaTrader files is not supported: {}", path.display())),
+        OutputFormat::Backtest(preset) => Err(format!("Reading {} files is not supported: {}", preset, path.display())),
+    }
+}
+
+// Read a Parquet file with the prices converted back from points
+pub fn read_parquet(file: File, path: &Path) -> Result<DataFrame, String> {
+    let read_error = |e: PolarsError| format!("Failed to read file {}: {}", path.display(), e);
+
+    let mut reader = ParquetReader::new(file);
+    let metadata = reader.get_metadata()
+        .map_err(read_error)?
+        .key_value_metadata()
+        .iter()
+        .flatten()
+        .map(|pair| (pair.key.clone(), pair.value.clone().unwrap_or_default()))
+        .collect::<Vec<(String, String)>>();
+    let df = reader.finish().map_err(read_error)?;
+
+    match recorded_scale(&metadata).map_err(|e| format!("{} in {}", e, path.display()))? {
+        Some(scale) => restore_prices(&df, scale),
+        None => Ok(df),
+    }
+}
+
+fn read_all(mut reader: impl std::io::Read, path: &Path) -> Result<Vec<u8>, String> {
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer).map_err(|e| format!("Failed to decompress {}: {}", path.display(), e))?;
+
+    Ok(buffer)
+}
+
+// Save the bars of the requested range
+// Returns the written files, or the database or directory for the formats that are not plain files
+pub fn save_data(
+    df: &DataFrame,
+    data_dir: &Path,
+    pair: &str,
+    format: OutputFormat,
+    range: (DateTime<Utc>, DateTime<Utc>),
+    options: &OutputOptions,
+    metadata: &[(String, String)],
+) -> Result<Vec<PathBuf>, String> {
+    save_chunks([Ok(df.clone())], data_dir, pair, format, range, options, metadata)
+}
+
+// Same as `save_data` for bars staged in sorted Parquet files that do not overlap
+// Only one staged file is loaded at a time
+pub fn save_staged(
+    chunks: &[PathBuf],
+    data_dir: &Path,
+    pair: &str,
+    format: OutputFormat,
+    range: (DateTime<Utc>, DateTime<Utc>),
+    options: &OutputOptions,
+    metadata: &[(String, String)],
+) -> Result<Vec<PathBuf>, String> {
+    save_chunks(chunks.iter().map(|path| read_staged(path)), data_dir, pair, format, range, options, metadata)
+}
+
+fn save_chunks(
+    chunks: impl IntoIterator<Item = Result<DataFrame, String>>,
+    data_dir: &Path,
+    pair: &str,
+    format: OutputFormat,
+    (from_date, to_date): (DateTime<Utc>, DateTime<Utc>),
+    options: &OutputOptions,
+    metadata: &[(String, String)],
+) -> Result<Vec<PathBuf>, String> {
+    // MetaTrader and the backtesting presets have their own price layout
+    if options.prices != PriceStorage::Float64 && matches!(format, OutputFormat::Mt4History | OutputFormat::Mt5Csv | OutputFormat::Backtest(_)) {
+        return Err(format!("Prices cannot be stored as {} in the {} format", options.prices, format));
+    }
+    let timezone = options.timezone.filter(|timezone| *timezone != Tz::UTC);
+    if let Some(timezone) = timezone {
+        // The hour repeated when daylight saving ends would overwrite bars
+        if matches!(format, OutputFormat::Sqlite | OutputFormat::ParquetDataset) || options.write_mode == WriteMode::Merge {
+            return Err(format!("Bars in {} cannot be merged with existing data, write them in UTC instead", timezone));
+        }
+        // MT4 needs ascending times, and the backtesting layouts are read as UTC
+        if matches!(format, OutputFormat::Mt4History | OutputFormat::Backtest(_)) {
+            return Err(format!("The {} format is written in UTC, not in {}", format, timezone));
+        }
+    }
+    let chunks = chunks.into_iter().map(|chunk| {
+        let df = chunk?;
+        let df = match timezone {
+            Some(timezone) => to_local_time(&df, timezone)?,
+            None => df,
+        };
+        compact_prices(&df, pair, options.prices)
+    });
+    let mut metadata = [metadata, &price_metadata(pair, options.prices)].concat();
+    // The local times repeat when daylight saving ends, so they are neither sorted nor unique
+    if let Some(timezone) = timezone {
+        for (key, value) in metadata.iter_mut() {
+            match key.as_str() {
+                "timezone" => *value = timezone.to_string(),
+                "datetime_order" => *value = "not monotonic".to_string(),
+                _ => {}
+            }
+        }
+    }
+    let metadata = &metadata;
+
+    match format {
+        // The database is shared by every instrument
+        OutputFormat::Sqlite => {
+            let database_path = data_dir.join(&options.sqlite.database);
+            for chunk in chunks {
+                write_sqlite(&chunk?, &database_path, pair, &options.sqlite, metadata)?;
+            }
+
+            return Ok(vec![database_path]);
+        }
+        OutputFormat::ParquetDataset => {
+            let mut dataset_path = None;
+            for chunk in chunks {
+                dataset_path = Some(write_partitioned(&chunk?, data_dir, pair, &options.parquet, metadata)?);
+            }
+
+            return Ok(dataset_path.into_iter().collect());
+        }
+        OutputFormat::Backtest(preset) => {
+            let mut writer = BacktestWriter::new(data_dir, pair, preset, options.write_mode)?;
+            for chunk in chunks {
+                writer.write(&chunk?)?;
+            }
+
+            return Ok(vec![writer.finish()?]);
+        }
+        _ => {}
+    }
+
+    let template = match (&options.file_name, options.split) {
+        (Some(template), _) => template.as_str(),
+        (None, SplitPeriod::None) => DEFAULT_FILE_NAME,
+        (None, _) => DEFAULT_SPLIT_FILE_NAME,
+    };
+    check_split_template(template, options.split)?;
+
+    // A period can span two chunks, its file stays open until the next period starts
+    let mut file_paths = Vec::new();
+    let mut current: Option<(String, OutputFile)> = None;
+    for chunk in chunks {
+        for part in split_by_period(&chunk?, options.split, from_date, to_date)? {
+            if current.as_ref().is_some_and(|(period, _)| *period != part.period) {
+                let (_, output_file) = current.take().unwrap();
+                file_paths.push(output_file.finish(metadata)?);
+            }
+
+            if current.is_none() {
+                // Get the paths
+                // MT4 only finds its history files under their own name
+                let file_name = match format {
+                    OutputFormat::Mt4History if options.file_name.is_none() && options.split == SplitPeriod::None => hst_file_name(pair),
+                    _ => render_file_name(template, pair, options.timeframe, format.extension(), &part),
+                };
+                current = Some((part.period.clone(), OutputFile::create(data_dir.join(file_name), format, options, pair)?));
+            }
+
+            current.as_mut().unwrap().1.write(&part.df)?;
+        }
+    }
+    if let Some((_, output_file)) = current {
+        file_paths.push(output_file.finish(metadata)?);
+    }
+
+    Ok(file_paths)
+}
+
+// Shift the UTC datetimes to the wall clock time of a timezone
+// The column stays a naive datetime, and the hour repeated
+// when daylight saving ends shows up twice
+pub fn to_local_time(df: &DataFrame, timezone: Tz) -> Result<DataFrame, String> {
+    let convert_error = |e: PolarsError| format!("Failed to convert datetimes to {}: {}", timezone, e);
+
+    let datetimes = df.column("datetime")
+        .and_then(|c| c.cast(&DataType::Datetime(TimeUnit::Microseconds, None)))
+        .map_err(convert_error)?;
+    let local = datetimes.datetime()
+        .map_err(convert_error)?
+        .iter()
+        .map(|micros| {
+            let utc = DateTime::from_timestamp_micros(micros?)?;
+            Some(utc.with_timezone(&timezone).naive_local().and_utc().timestamp_micros())
+        })
+        .collect::<Int64Chunked>()
+        .into_datetime(TimeUnit::Microseconds, None);
+
+    let mut df = df.clone();
+    df.with_column(local.into_series().with_name("datetime".into())).map_err(convert_error)?;
+
+    Ok(df)
+}
+
+// A file written next to its final path, then renamed
+// so an interrupted run never leaves a truncated file
+// The write mode is applied to the final path, merging is left to the caller
+pub struct PendingFile {
+    file_path: PathBuf,
+    temp_path: PathBuf,
+}
+
+impl PendingFile {
+    pub fn create(file_path: PathBuf, write_mode: WriteMode) -> Result<(PendingFile, File), String> {
+        if let Some(parent) = file_path.parent() {
+            create_dir_all(parent).map_err(|e| format!("Failed to create directory {}: {}", parent.display(), e))?;
+        }
+        if write_mode == WriteMode::ErrorIfExists && file_path.exists() {
+            return Err(format!("{} already exists, choose to overwrite or merge it", file_path.display()));
+        }
+
+        let temp_path = file_path.with_file_name(format!("{}.tmp", file_path.file_name().unwrap().to_string_lossy()));
+        let file = File::create(&temp_path).map_err(|e| format!("Failed to create file {}: {}", temp_path.display(), e))?;
+
+        Ok((PendingFile { file_path, temp_path }, file))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.file_path
+    }
+
+    // Replace the final file once the new one is complete
+    pub fn commit(self) -> Result<PathBuf, String> {
+        rename(&self.temp_path, &self.file_path).map_err(|e| format!("Failed to replace {}: {}", self.file_path.display(), e))?;
+
+        Ok(self.file_path.clone())
+    }
+}
+
+// Leave nothing behind when the file could not be written
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.temp_path);
+    }
+}
+
+// A plain file of bars, see `PendingFile`
+// The writer is declared first so the file is closed before it is removed
+struct OutputFile<'a> {
+    writer: Option<ChunkWriter<'a>>,
+    pending: PendingFile,
+    format: OutputFormat,
+    // The new bars, kept until the end when merging into an existing file
+    merged: Option<Vec<DataFrame>>,
+}
+
+impl<'a> OutputFile<'a> {
+    fn create(file_path: PathBuf, format: OutputFormat, options: &'a OutputOptions, pair: &'a str) -> Result<Self, String> {
+        let merged = if options.write_mode == WriteMode::Merge && file_path.exists() {
+            check_mergeable(&file_path, format, options)?;
+            Some(Vec::new())
+        } else {
+            None
+        };
+        let (pending, file) = PendingFile::create(file_path, options.write_mode)?;
+
+        Ok(OutputFile {
+            writer: Some(ChunkWriter::new(file, format, options, pair)?),
+            pending,
+            format,
+            merged,
+        })
+    }
+
+    fn write(&mut self, df: &DataFrame) -> Result<(), String> {
+        match &mut self.merged {
+            Some(merged) => {
+                merged.push(df.clone());
+                Ok(())
+            }
+            None => self.writer.as_mut().unwrap().write(df),
+        }
+    }
+
+    fn finish(mut self, metadata: &[(String, String)]) -> Result<PathBuf, String> {
+        let mut writer = self.writer.take().unwrap();
+        let file_path = self.pending.path();
+
+        // Merging needs the whole file in memory
+        if let Some(chunks) = self.merged.take() {
+            let mut new_bars = DataFrame::empty_with_schema(chunks[0].schema());
+            for chunk in &chunks {
+                new_bars.vstack_mut(chunk).map_err(|e| format!("Failed to merge with {}: {}", file_path.display(), e))?;
+            }
+            new_bars.align_chunks_par();
+            let merged = merge_existing(file_path, self.format, &new_bars, writer.pair, writer.options.prices)?;
+            writer.write(&merged)?;
+        }
+        writer.finish(metadata)?;
+
+        self.pending.commit()
+    }
+}
+
+// Path written by `save_data` without a template or a split
+// `None` for the outputs that are always merged
+pub fn default_output_path(data_dir: &Path, pair: &str, format: OutputFormat) -> Option<PathBuf> {
+    match format {
+        OutputFormat::Sqlite | OutputFormat::ParquetDataset => None,
+        OutputFormat::Backtest(preset) => Some(backtest_path(data_dir, pair, preset)),
+        OutputFormat::Mt4History => Some(data_dir.join(hst_file_name(pair))),
+        _ => Some(data_dir.join(format!("{}.{}", pair, format.extension()))),
+    }
+}
+
+// The reports written next to the data follow its write mode
+// SQLite databases and datasets always merge, so their reports are replaced
+pub fn report_write_mode(format: OutputFormat, write_mode: WriteMode) -> WriteMode {
+    match format {
+        OutputFormat::Sqlite | OutputFormat::ParquetDataset => WriteMode::Overwrite,
+        _ => write_mode,
+    }
+}
+
+// Only the files read back by `read_data` can be merged
+fn check_mergeable(file_path: &Path, format: OutputFormat, options: &OutputOptions) -> Result<(), String> {
+    match format {
+        OutputFormat::Csv | OutputFormat::CsvGzip | OutputFormat::CsvZstd if options.csv != CsvOptions::default() => {
+            Err(format!("Merging into {} needs the default CSV dialect", file_path.display()))
+        }
+        OutputFormat::Mt4History | OutputFormat::Mt5Csv => {
+            Err(format!("Merging into {} is not supported, overwrite it instead", file_path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Read the existing file back and combine it with the new bars
+// The new bars replace the existing ones with the same timestamp, so both
+// are deduplicated and sorted together, in memory
+// The existing prices are converted to the storage of the new ones, or the merge
+// is refused when they do not fit it, then the other columns are cast
+fn merge_existing(file_path: &Path, format: OutputFormat, df: &DataFrame, pair: &str, storage: PriceStorage) -> Result<DataFrame, String> {
+    let merge_error = |e: PolarsError| format!("Failed to merge with {}: {}", file_path.display(), e);
+
+    let existing = read_data(file_path, format)?;
+    // `read_data` already converted the points of a Parquet file, the points
+    // of the other formats were written with the scale of the instrument
+    let scale = stored_as_points(&existing).then(|| price_scale(pair));
+    let existing = convert_prices(&existing, scale, pair, storage)
+        .map_err(|e| format!("Cannot merge {} prices with {}: {}", storage, file_path.display(), e))?;
+
+    let columns = df.schema()
+        .iter()
+        .map(|(name, dtype)| col(name.clone()).cast(dtype.clone()))
+        .collect::<Vec<Expr>>();
+    let existing = existing
+        .lazy()
+        .select(columns)
+        .collect()
+        .map_err(merge_error)?;
+
+    existing
+        .vstack(df)
+        .and_then(|merged| merged.unique_stable(Some(&["datetime".to_string()]), UniqueKeepStrategy::Last, None))
+        .and_then(|merged| merged.sort(["datetime"], Default::default()))
+        .map_err(merge_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::dataset::scan_partitioned;
+
+    use rusqlite::Connection;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    const PAIR: &str = "EURUSD";
+
+    // Three bars across a year boundary, in microseconds
+    const MICROS: [i64; 3] = [1_577_836_740_000_000, 1_577_836_800_000_000, 1_577_836_860_000_000];
+    const CLOSES: [f64; 3] = [1.12127, 1.12131, 1.12098];
+    const VOLUMES: [i64; 3] = [0, 3, 7];
+
+    fn sample_bars() -> DataFrame {
+        DataFrame::new(vec![
+            Column::new("datetime".into(), MICROS.to_vec()).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap(),
+            Column::new("open".into(), vec![1.12120, 1.12127, 1.12131]),
+            Column::new("high".into(), vec![1.12130, 1.12140, 1.12135]),
+            Column::new("low".into(), vec![1.12115, 1.12125, 1.12090]),
+            Column::new("close".into(), CLOSES.to_vec()),
+            Column::new("volume".into(), VOLUMES.to_vec()),
+        ])
+        .unwrap()
+    }
+
+    fn range() -> (DateTime<Utc>, DateTime<Utc>) {
+        (DateTime::from_timestamp_micros(MICROS[0]).unwrap(), DateTime::from_timestamp_micros(MICROS[2]).unwrap())
+    }
+
+    fn save(data_dir: &Path, format: OutputFormat) -> PathBuf {
+        let file_paths = save_data(&sample_bars(), data_dir, PAIR, format, range(), &OutputOptions::default(), &[]).unwrap();
+        assert_eq!(file_paths.len(), 1, "{}", format);
+
+        file_paths.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn plain_files_round_trip() {
+        let dir = tempdir().unwrap();
+        let formats = [
+            OutputFormat::Csv,
+            OutputFormat::CsvGzip,
+            OutputFormat::CsvZstd,
+            OutputFormat::Parquet,
+            OutputFormat::ArrowIpc,
+            OutputFormat::NdJson,
+        ];
+
+        for format in formats {
+            let path = dir.path().join(format!("{}.{}", PAIR, format.extension()));
+            let metadata = [("symbol".to_string(), PAIR.to_string())];
+            write_data(&mut sample_bars(), File::create(&path).unwrap(), format, &OutputOptions::default(), &metadata).unwrap();
+
+            let df = read_data(&path, format).unwrap();
+            assert_eq!(df.column("datetime").unwrap().dtype(), &DataType::Datetime(TimeUnit::Microseconds, None), "{}", format);
+            assert!(df.equals(&sample_bars()), "{} read back as {}", format, df);
+        }
+    }
+
+    #[test]
+    fn the_chunks_of_a_plain_file_are_appended() {
+        let dir = tempdir().unwrap();
+        let df = sample_bars();
+
+        for format in [OutputFormat::Csv, OutputFormat::Parquet, OutputFormat::ArrowIpc, OutputFormat::NdJson] {
+            let chunks = [Ok(df.slice(0, 1)), Ok(df.slice(1, 2))];
+            let file_paths = save_chunks(chunks, dir.path(), PAIR, format, range(), &OutputOptions::default(), &[]).unwrap();
+
+            assert!(read_data(&file_paths[0], format).unwrap().equals(&df), "{}", format);
+        }
+    }
+
+    #[test]
+    fn a_compression_level_out_of_range_is_refused() {
+        let options = |codec: ParquetCodec, level: u32| ParquetOptions { codec, level: Some(level), ..Default::default() };
+
+        assert!(options(ParquetCodec::Gzip, 9).compression().is_ok());
+        assert_eq!(options(ParquetCodec::Gzip, 256).compression().unwrap_err(), "Invalid compression level: 256");
+        assert!(options(ParquetCodec::Gzip, 11).compression().unwrap_err().starts_with("Invalid compression level: 11: "));
+        assert_eq!(options(ParquetCodec::Zstd, u32::MAX).compression().unwrap_err(), format!("Invalid compression level: {}", u32::MAX));
+        assert!(options(ParquetCodec::Brotli, 12).compression().is_err());
+    }
+
+    #[test]
+    fn points_are_read_back_as_prices() {
+        let dir = tempdir().unwrap();
+        let options = OutputOptions { prices: PriceStorage::Points, ..Default::default() };
+        let file_paths = save_data(&sample_bars(), dir.path(), PAIR, OutputFormat::Parquet, range(), &options, &[]).unwrap();
+
+        assert!(read_data(&file_paths[0], OutputFormat::Parquet).unwrap().equals(&sample_bars()));
+    }
+
+    #[test]
+    fn merged_prices_are_converted_to_the_new_storage() {
+        let storages = [PriceStorage::Float64, PriceStorage::Float32, PriceStorage::Points];
+
+        for format in [OutputFormat::Parquet, OutputFormat::Csv, OutputFormat::ArrowIpc] {
+            for (existing, new) in storages.iter().flat_map(|existing| storages.iter().map(move |new| (*existing, *new))) {
+                let dir = tempdir().unwrap();
+                let df = sample_bars();
+                let save = |df: &DataFrame, prices: PriceStorage| {
+                    let options = OutputOptions { write_mode: WriteMode::Merge, prices, ..Default::default() };
+                    save_data(df, dir.path(), PAIR, format, range(), &options, &[]).unwrap()
+                };
+                save(&df.slice(0, 2), existing);
+                let file_paths = save(&df.slice(2, 1), new);
+
+                // Only Parquet converts the points back by itself
+                let merged = read_data(&file_paths[0], format).unwrap();
+                let merged = convert_prices(&merged, stored_as_points(&merged).then(|| price_scale(PAIR)), PAIR, PriceStorage::Float64).unwrap();
+                assert!(merged.equals(&df), "{} {} merged into {}: {}", format, new, existing, merged);
+            }
+        }
+    }
+
+    #[test]
+    fn prices_that_do_not_fit_the_points_are_not_merged() {
+        let dir = tempdir().unwrap();
+        let mut df = sample_bars();
+        df.with_column(Column::new("close".into(), vec![1.121275, 1.12131, 1.12098])).unwrap();
+        save_data(&df, dir.path(), PAIR, OutputFormat::Parquet, range(), &OutputOptions::default(), &[]).unwrap();
+
+        let options = OutputOptions { write_mode: WriteMode::Merge, prices: PriceStorage::Points, ..Default::default() };
+        let error = save_data(&sample_bars(), dir.path(), PAIR, OutputFormat::Parquet, range(), &options, &[]).unwrap_err();
+        assert!(error.contains("Cannot merge points prices"), "{}", error);
+    }
+
+    #[test]
+    fn a_database_keeps_one_price_storage() {
+        let dir = tempdir().unwrap();
+        let options = OutputOptions { prices: PriceStorage::Points, ..Default::default() };
+        save_data(&sample_bars(), dir.path(), PAIR, OutputFormat::Sqlite, range(), &options, &[]).unwrap();
+
+        let error = save_data(&sample_bars(), dir.path(), PAIR, OutputFormat::Sqlite, range(), &OutputOptions::default(), &[]).unwrap_err();
+        assert!(error.contains("stored as points, not float64"), "{}", error);
+    }
+
+    #[test]
+    fn parquet_dataset_round_trip() {
+        let dir = tempdir().unwrap();
+        save(dir.path(), OutputFormat::ParquetDataset);
+
+        let df = scan_partitioned(dir.path())
+            .unwrap()
+            .select([col("datetime"), col("open"), col("high"), col("low"), col("close"), col("volume")])
+            .sort(["datetime"], Default::default())
+            .collect()
+            .unwrap();
+        assert!(df.equals(&sample_bars()), "read back as {}", df);
+    }
+
+    #[test]
+    fn sqlite_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = save(dir.path(), OutputFormat::Sqlite);
+
+        let connection = Connection::open(path).unwrap();
+        let mut statement = connection.prepare("SELECT \"datetime\", \"close\", \"volume\" FROM \"EURUSD\" ORDER BY \"datetime\"").unwrap();
+        let rows = statement
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?, row.get::<_, i64>(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let expected = (0..3).map(|i| (MICROS[i] / 1_000_000, CLOSES[i], VOLUMES[i])).collect::<Vec<_>>();
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn mt4_history_round_trip() {
+        let dir = tempdir().unwrap();
+        let bytes = std::fs::read(save(dir.path(), OutputFormat::Mt4History)).unwrap();
+
+        assert_eq!(bytes.len(), 148 + 3 * 60);
+        assert_eq!(i32::from_le_bytes(bytes[0..4].try_into().unwrap()), 401);
+        for (i, record) in bytes[148..].chunks(60).enumerate() {
+            let time = i64::from_le_bytes(record[0..8].try_into().unwrap());
+            let close = f64::from_le_bytes(record[32..40].try_into().unwrap());
+            let volume = i64::from_le_bytes(record[40..48].try_into().unwrap());
+            assert_eq!((time, close, volume), (MICROS[i] / 1_000_000, CLOSES[i], VOLUMES[i]));
+        }
+    }
+
+    #[test]
+    fn mt5_csv_round_trip() {
+        let dir = tempdir().unwrap();
+        let text = std::fs::read_to_string(save(dir.path(), OutputFormat::Mt5Csv)).unwrap();
+        let lines = text.lines().collect::<Vec<&str>>();
+
+        assert_eq!(lines[0], "<DATE>\t<TIME>\t<OPEN>\t<HIGH>\t<LOW>\t<CLOSE>\t<TICKVOL>\t<VOL>\t<SPREAD>");
+        assert_eq!(lines[1], "2019.12.31\t23:59:00\t1.12120\t1.12130\t1.12115\t1.12127\t0\t0\t0");
+        assert_eq!(lines[2], "2020.01.01\t00:00:00\t1.12127\t1.12140\t1.12125\t1.12131\t3\t0\t0");
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn backtest_presets_round_trip() {
+        let dir = tempdir().unwrap();
+
+        let backtrader = std::fs::read_to_string(save(dir.path(), OutputFormat::Backtest(BacktestPreset::Backtrader))).unwrap();
+        assert_eq!(
+            backtrader.lines().take(2).collect::<Vec<&str>>(),
+            ["datetime,open,high,low,close,volume,openinterest", "2019-12-31 23:59:00,1.1212,1.1213,1.12115,1.12127,0,0"],
+        );
+
+        // NinjaTrader stamps the bars at their end
+        let ninjatrader = std::fs::read_to_string(save(dir.path(), OutputFormat::Backtest(BacktestPreset::NinjaTrader))).unwrap();
+        assert_eq!(ninjatrader.lines().next(), Some("20200101 000000;1.1212;1.1213;1.12115;1.12127;0"));
+        assert_eq!(ninjatrader.lines().count(), 3);
+
+        let zipline = std::fs::read_to_string(save(dir.path(), OutputFormat::Backtest(BacktestPreset::Zipline))).unwrap();
+        assert_eq!(zipline.lines().next(), Some("date,open,high,low,close,volume,dividend,split"));
+        assert_eq!(zipline.lines().last(), Some("2020-01-01 00:01:00,1.12131,1.12135,1.1209,1.12098,7,0.0,1.0"));
+
+        // LEAN writes one zip per UTC day
+        let lean_dir = save(dir.path(), OutputFormat::Backtest(BacktestPreset::Lean));
+        let read_day = |date: &str| {
+            let mut archive = ::zip::ZipArchive::new(File::open(lean_dir.join(format!("{}_quote.zip", date))).unwrap()).unwrap();
+            let mut lines = String::new();
+            archive.by_name(&format!("{}_eurusd_minute_quote.csv", date)).unwrap().read_to_string(&mut lines).unwrap();
+            lines
+        };
+        assert_eq!(read_day("20191231"), "86340000,1.12120,1.12130,1.12115,1.12127,1.12120,1.12130,1.12115,1.12127\n");
+        assert_eq!(read_day("20200101").lines().collect::<Vec<&str>>(), [
+            "0,1.12127,1.12140,1.12125,1.12131,1.12127,1.12140,1.12125,1.12131",
+            "60000,1.12131,1.12135,1.12090,1.12098,1.12131,1.12135,1.12090,1.12098",
+        ]);
+    }
+
+    #[test]
+    fn backtests_follow_the_write_mode() {
+        let dir = tempdir().unwrap();
+        let save_backtest = |preset: BacktestPreset, write_mode: WriteMode| {
+            // The second chunk starts in the middle of the last UTC day
+            let df = sample_bars();
+            let chunks = [Ok(df.slice(0, 2)), Ok(df.slice(2, 1))];
+            let options = OutputOptions { write_mode, ..Default::default() };
+            save_chunks(chunks, dir.path(), PAIR, OutputFormat::Backtest(preset), range(), &options, &[])
+        };
+
+        let lean_dir = save_backtest(BacktestPreset::Lean, WriteMode::ErrorIfExists).unwrap().remove(0);
+        let mut archive = ::zip::ZipArchive::new(File::open(lean_dir.join("20200101_quote.zip")).unwrap()).unwrap();
+        let mut lines = String::new();
+        archive.by_name("20200101_eurusd_minute_quote.csv").unwrap().read_to_string(&mut lines).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+
+        for preset in BacktestPreset::all() {
+            let path = save_backtest(preset, WriteMode::Overwrite).unwrap().remove(0);
+            assert_eq!(Some(path), default_output_path(dir.path(), PAIR, OutputFormat::Backtest(preset)));
+
+            assert!(save_backtest(preset, WriteMode::ErrorIfExists).unwrap_err().contains("already exists"), "{}", preset);
+            assert!(save_backtest(preset, WriteMode::Merge).unwrap_err().contains("is not supported"), "{}", preset);
+        }
+
+        // Every file was renamed once complete
+        let temp_files = walk(dir.path()).into_iter().filter(|path| path.extension().is_some_and(|extension| extension == "tmp")).count();
+        assert_eq!(temp_files, 0);
+    }
+
+    fn walk(dir: &Path) -> Vec<PathBuf> {
+        std::fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .flat_map(|path| if path.is_dir() { walk(&path) } else { vec![path] })
+            .collect()
+    }
+
+    #[test]
+    fn local_times_are_refused_where_they_cannot_repeat() {
+        let dir = tempdir().unwrap();
+        let save_local = |format: OutputFormat, write_mode: WriteMode| {
+            let options = OutputOptions { write_mode, timezone: Some(chrono_tz::America::New_York), ..Default::default() };
+            let metadata = [("timezone".to_string(), "UTC".to_string()), ("datetime_order".to_string(), "ascending,unique".to_string())];
+            save_data(&sample_bars(), dir.path(), PAIR, format, range(), &options, &metadata)
+        };
+
+        for format in [OutputFormat::Sqlite, OutputFormat::ParquetDataset, OutputFormat::Mt4History, OutputFormat::Backtest(BacktestPreset::Lean)] {
+            assert!(save_local(format, WriteMode::ErrorIfExists).is_err(), "{}", format);
+        }
+        assert!(save_local(OutputFormat::Parquet, WriteMode::Merge).is_err());
+
+        let path = save_local(OutputFormat::Parquet, WriteMode::ErrorIfExists).unwrap().remove(0);
+        let metadata = ParquetReader::new(File::open(&path).unwrap()).get_metadata().unwrap().key_value_metadata().clone().unwrap();
+        let value = |key: &str| metadata.iter().find(|pair| pair.key == key).and_then(|pair| pair.value.clone());
+        assert_eq!(value("timezone").as_deref(), Some("America/New_York"));
+        assert_eq!(value("datetime_order").as_deref(), Some("not monotonic"));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
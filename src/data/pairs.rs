@@ -95,4 +95,19 @@ pub async fn build_pairs() {
     insert!("XAUUSD", 2009);
     insert!("XAGUSD", 2009);
     insert!("ZARJPY", 2010);
-}
\ No newline at end of file
+}
+
+// Number of decimals quoted for an instrument
+// Yen and forint crosses, silver, oil and the dollar index are quoted with 3 decimals,
+// gold and the stock indices with 2
+pub fn price_digits(pair: &str) -> u32 {
+    let pair = pair.replace("/", "").to_uppercase();
+
+    match pair.as_str() {
+        "UDXUSD" | "BCOUSD" | "WTIUSD" | "XAGUSD" => 3,
+        "AUXAUD" | "ETXEUR" | "FRXEUR" | "GRXEUR" | "HKXHKD" | "JPXJPY" | "NSXUSD" | "SPXUSD" | "UKXGBP" => 2,
+        _ if pair.starts_with("XAU") => 2,
+        _ if pair.ends_with("JPY") || pair.ends_with("HUF") => 3,
+        _ => 5,
+    }
+}
@@ -0,0 +1,233 @@
+use crate::data::pairs::price_digits;
+
+use polars::prelude::*;
+use std::{fmt, str::FromStr};
+
+pub const PRICE_COLUMNS: [&str; 4] = ["open", "high", "low", "close"];
+
+// How the prices are stored in the output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PriceStorage {
+    #[default]
+    Float64,
+    // Half the size, enough for the 5 or 6 significant digits of a quote
+    Float32,
+    // Integer number of points, the smallest price step of the instrument
+    // `1.14640` is stored as `114640` with a scale of 100000
+    Points,
+}
+
+impl fmt::Display for PriceStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PriceStorage::Float64 => "float64",
+            PriceStorage::Float32 => "float32",
+            PriceStorage::Points => "points",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for PriceStorage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "float64" | "f64" => Ok(PriceStorage::Float64),
+            "float32" | "f32" => Ok(PriceStorage::Float32),
+            "points" | "integer" | "int" => Ok(PriceStorage::Points),
+            _ => Err(format!("Unknown price storage: {}", s)),
+        }
+    }
+}
+
+// Number of points in one unit of price
+pub fn price_scale(pair: &str) -> i64 {
+    10i64.pow(price_digits(pair))
+}
+
+// Convert the price columns to the wanted storage
+// Converting to points fails if a price has more decimals than the instrument,
+// so that converting back with `restore_prices` gives the same prices
+pub fn compact_prices(df: &DataFrame, pair: &str, storage: PriceStorage) -> Result<DataFrame, String> {
+    let mut df = df.clone();
+
+    for name in PRICE_COLUMNS {
+        let column = df.column(name).map_err(|e| format!("Failed to read {} prices: {}", name, e))?;
+        let float32 = column.dtype() == &DataType::Float32;
+        let mut column = column.cast(&DataType::Float64).map_err(|e| format!("Failed to read {} prices: {}", name, e))?;
+        // 32-bit prices read back are rounded to the points of the instrument,
+        // so their binary noise does not reach the `f64` prices
+        if float32 && storage != PriceStorage::Float32 {
+            let scale = price_scale(pair) as f64;
+            let prices = column.f64()
+                .map_err(|e| format!("Failed to read {} prices: {}", name, e))?
+                .apply_values(|price| (price * scale).round() / scale);
+            column = prices.with_name(name.into()).into_column();
+        }
+
+        let compacted = match storage {
+            PriceStorage::Float64 => column,
+            PriceStorage::Float32 => column.cast(&DataType::Float32).map_err(|e| format!("Failed to convert {} prices: {}", name, e))?,
+            PriceStorage::Points => {
+                let points = column.f64()
+                    .map_err(|e| format!("Failed to read {} prices: {}", name, e))?
+                    .iter()
+                    .map(|price| price.map(|price| to_points(price, pair)).transpose())
+                    .collect::<Result<Int32Chunked, String>>()?;
+
+                points.with_name(name.into()).into_column()
+            }
+        };
+
+        df.with_column(compacted).map_err(|e| format!("Failed to replace {} prices: {}", name, e))?;
+    }
+
+    Ok(df)
+}
+
+// Convert prices stored as points back to `f64`
+// The scale is the `price_scale` recorded in the metadata of the file
+pub fn restore_prices(df: &DataFrame, scale: i64) -> Result<DataFrame, String> {
+    let mut df = df.clone();
+
+    for name in PRICE_COLUMNS {
+        // The division of an integer by a power of ten gives the closest `f64`,
+        // the same value as parsing the original decimal price
+        let prices = df.column(name)
+            .and_then(|c| c.cast(&DataType::Float64))
+            .and_then(|c| c.f64().cloned())
+            .map_err(|e| format!("Failed to read {} points: {}", name, e))?
+            .apply_values(|points| points / scale as f64);
+
+        df.with_column(prices.with_name(name.into()).into_column()).map_err(|e| format!("Failed to replace {} prices: {}", name, e))?;
+    }
+
+    Ok(df)
+}
+
+// Convert prices read back from an existing file to the storage of the new bars
+// The points are converted back with the scale the file was written with, then
+// stored like the new bars, which fails if they have more decimals than the instrument
+pub fn convert_prices(df: &DataFrame, scale: Option<i64>, pair: &str, storage: PriceStorage) -> Result<DataFrame, String> {
+    let df = match scale {
+        Some(scale) => restore_prices(df, scale)?,
+        None if stored_as_points(df) => return Err("The prices are stored as points without a recorded scale".into()),
+        None => df.clone(),
+    };
+
+    compact_prices(&df, pair, storage)
+}
+
+// Whether the price columns hold integer points
+pub fn stored_as_points(df: &DataFrame) -> bool {
+    PRICE_COLUMNS.iter().any(|name| df.column(name).is_ok_and(|c| c.dtype().is_integer()))
+}
+
+// Storage recorded by `price_metadata`
+// Files written before it was recorded hold `f64` prices
+pub fn recorded_storage(metadata: &[(String, String)]) -> Result<PriceStorage, String> {
+    match metadata.iter().find(|(key, _)| key == "price_storage") {
+        Some((_, value)) => value.parse(),
+        None => Ok(PriceStorage::Float64),
+    }
+}
+
+// Scale recorded by `price_metadata`, `None` unless the prices are points
+pub fn recorded_scale(metadata: &[(String, String)]) -> Result<Option<i64>, String> {
+    if recorded_storage(metadata)? != PriceStorage::Points {
+        return Ok(None);
+    }
+
+    metadata.iter()
+        .find(|(key, _)| key == "price_scale")
+        .and_then(|(_, value)| value.parse::<i64>().ok())
+        .filter(|scale| *scale > 0)
+        .map(Some)
+        .ok_or_else(|| "The prices are stored as points without a valid price_scale".to_string())
+}
+
+// Describe the price storage so the prices can be read back
+pub fn price_metadata(pair: &str, storage: PriceStorage) -> Vec<(String, String)> {
+    let mut metadata = vec![("price_storage".to_string(), storage.to_string())];
+    if storage == PriceStorage::Points {
+        metadata.push(("price_scale".to_string(), price_scale(pair).to_string()));
+        metadata.push(("price_digits".to_string(), price_digits(pair).to_string()));
+    }
+
+    metadata
+}
+
+fn to_points(price: f64, pair: &str) -> Result<i32, String> {
+    let scaled = price * price_scale(pair) as f64;
+    let points = scaled.round();
+
+    // A tenth of a point leaves room for the error of the multiplication
+    if (points - scaled).abs() > 0.1 {
+        return Err(format!("Price {} of {} has more than {} decimals and cannot be stored as points", price, pair, price_digits(pair)));
+    }
+    if points < i32::MIN as f64 || points > i32::MAX as f64 {
+        return Err(format!("Price {} is too large to be stored as points", price));
+    }
+
+    Ok(points as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bars(prices: &[f64]) -> DataFrame {
+        DataFrame::new(PRICE_COLUMNS.iter().map(|name| Column::new((*name).into(), prices.to_vec())).collect()).unwrap()
+    }
+
+    #[test]
+    fn points_are_restored_to_the_same_prices() {
+        let cases: [(&str, u32, &[f64]); 3] = [
+            ("XAUUSD", 2, &[1517.27, 1517.3, 2063.01, 0.01]),
+            ("USDJPY", 3, &[108.751, 109.0, 110.123, 99.999]),
+            ("EURUSD", 5, &[1.12127, 1.1213, 0.99999, 1.60381]),
+        ];
+
+        for (pair, digits, prices) in cases {
+            assert_eq!(price_digits(pair), digits);
+            let points = compact_prices(&bars(prices), pair, PriceStorage::Points).unwrap();
+            assert_eq!(points.column("close").unwrap().dtype(), &DataType::Int32, "{}", pair);
+
+            let restored = restore_prices(&points, price_scale(pair)).unwrap();
+            assert!(restored.equals(&bars(prices)), "{} restored as {}", pair, restored);
+        }
+    }
+
+    #[test]
+    fn prices_with_more_decimals_than_points_are_refused() {
+        let error = compact_prices(&bars(&[1.121275]), "EURUSD", PriceStorage::Points).unwrap_err();
+
+        assert!(error.contains("more than 5 decimals"), "{}", error);
+    }
+
+    #[test]
+    fn float32_prices_are_rounded_to_the_points() {
+        let float32 = compact_prices(&bars(&[1.12127, 108.751]), "EURUSD", PriceStorage::Float32).unwrap();
+        let restored = convert_prices(&float32, None, "EURUSD", PriceStorage::Float64).unwrap();
+
+        assert!(restored.equals(&bars(&[1.12127, 108.751])), "{}", restored);
+    }
+
+    #[test]
+    fn points_need_their_scale() {
+        let points = compact_prices(&bars(&[1.12127]), "EURUSD", PriceStorage::Points).unwrap();
+
+        assert!(convert_prices(&points, None, "EURUSD", PriceStorage::Float64).is_err());
+        assert!(convert_prices(&points, Some(100_000), "EURUSD", PriceStorage::Float64).unwrap().equals(&bars(&[1.12127])));
+    }
+
+    #[test]
+    fn the_recorded_storage_is_read_back() {
+        assert_eq!(recorded_storage(&[]).unwrap(), PriceStorage::Float64);
+        assert_eq!(recorded_scale(&price_metadata("USDJPY", PriceStorage::Points)).unwrap(), Some(1000));
+        assert_eq!(recorded_scale(&price_metadata("USDJPY", PriceStorage::Float32)).unwrap(), None);
+        assert!(recorded_scale(&[("price_storage".to_string(), "points".to_string())]).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use crate::data::{
+    archive::{ArchiveLimits, expected_entry_name, read_archive},
+    gaps::{Gap, parse_gap_report},
+};
+
+use chrono::NaiveDate;
+use polars::prelude::*;
+use std::{io::BufRead, path::Path};
+
+// How the downloaded archives are read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadOptions {
+    // Limits checked before parsing an archive
+    pub archive_limits: ArchiveLimits,
+}
+
+// Read a HistData ASCII M1 file straight into typed columns
+// The lines look like `20190101 170000;1.146400;1.146600;1.146300;1.146500;0`
+// The timestamps are left in the source timezone
+// A malformed line fails the read with its line number
+pub fn read_m1<R: BufRead>(mut reader: R) -> Result<DataFrame, String> {
+    let mut datetimes = Vec::new();
+    let mut prices: [Vec<f64>; 4] = Default::default();
+    let mut volumes = Vec::new();
+
+    // The date only changes once a day, so it is parsed once per day
+    let mut day: Option<([u8; 8], i64)> = None;
+    let mut line = Vec::with_capacity(64);
+    let mut line_number = 0;
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line).map_err(|e| format!("Failed to read M1 data: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |reason: &str| format!("Invalid M1 bar on line {}: {} in '{}'", line_number, reason, String::from_utf8_lossy(line));
+
+        let mut fields = line.split(|byte| *byte == b';');
+        let datetime = fields.next().ok_or_else(|| error("missing datetime"))?;
+        if datetime.len() != 15 || datetime[8] != b' ' {
+            return Err(error("expected a datetime like YYYYMMDD HHMMSS"));
+        }
+
+        let date: [u8; 8] = datetime[..8].try_into().unwrap();
+        let day_micros = match day {
+            Some((cached, micros)) if cached == date => micros,
+            _ => {
+                let micros = parse_date(&date).ok_or_else(|| error("invalid date"))?;
+                day = Some((date, micros));
+                micros
+            }
+        };
+        let (hours, minutes, seconds) = match (digits(&datetime[9..11]), digits(&datetime[11..13]), digits(&datetime[13..15])) {
+            (Some(h), Some(m), Some(s)) if h < 24 && m < 60 && s < 60 => (h, m, s),
+            _ => return Err(error("invalid time")),
+        };
+        datetimes.push(day_micros + ((hours * 60 + minutes) * 60 + seconds) as i64 * 1_000_000);
+
+        for (name, column) in ["open", "high", "low", "close"].iter().zip(prices.iter_mut()) {
+            let price = fields.next()
+                .and_then(|field| std::str::from_utf8(field.trim_ascii()).ok())
+                .and_then(|field| field.parse::<f64>().ok())
+                .filter(|price| price.is_finite())
+                .ok_or_else(|| error(&format!("invalid {} price", name)))?;
+            column.push(price);
+        }
+
+        let volume = fields.next()
+            .and_then(|field| std::str::from_utf8(field.trim_ascii()).ok())
+            .and_then(|field| field.parse::<i64>().ok())
+            .ok_or_else(|| error("invalid volume"))?;
+        volumes.push(volume);
+
+        if fields.next().is_some() {
+            return Err(error("too many fields"));
+        }
+    }
+
+    let datetime = Column::new("datetime".into(), datetimes)
+        .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+        .map_err(|e| format!("Failed to build datetime column: {}", e))?;
+    let mut columns = vec![datetime];
+    for (name, column) in ["open", "high", "low", "close"].into_iter().zip(prices) {
+        columns.push(Column::new(name.into(), column));
+    }
+    columns.push(Column::new("volume".into(), volumes));
+
+    DataFrame::new(columns).map_err(|e| format!("Failed to build M1 bars: {}", e))
+}
+
+// Read the bars and the gap report of a HistData zip without extracting it
+// The archive is checked against the limits before anything is parsed
+pub fn read_m1_zip(path: &Path, pair: &str, year: usize, options: &ReadOptions) -> Result<(DataFrame, Vec<Gap>), String> {
+    let archive = read_archive(path, &expected_entry_name(pair, year), &options.archive_limits)?;
+
+    let bars = read_m1(archive.data.as_slice())
+        .map_err(|e| format!("{} in {}", e, path.display()))?;
+    let gaps = archive.reports.iter().flat_map(|report| parse_gap_report(report)).collect();
+
+    Ok((bars, gaps))
+}
+
+// Midnight of a `YYYYMMDD` date, in microseconds
+fn parse_date(date: &[u8; 8]) -> Option<i64> {
+    let year = digits(&date[..4])?;
+    let month = digits(&date[4..6])?;
+    let day = digits(&date[6..8])?;
+
+    NaiveDate::from_ymd_opt(year as i32, month, day)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc().timestamp_micros())
+}
+
+fn digits(bytes: &[u8]) -> Option<u32> {
+    bytes.iter().try_fold(0, |value, byte| {
+        byte.is_ascii_digit().then(|| value * 10 + (byte - b'0') as u32)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, sync::Arc};
+    use tempfile::NamedTempFile;
+
+    const LINES: &str = "20200228 235800;1.099530;1.099610;1.099480;1.099600;0
+20200228 235900;1.099600;1.099700;1.099550;1.099650;12
+20200229 000000; 1.099650;1.099700 ;1.099600;1.099610;3
+20200302 170000;1.113400;1.113600;1.113300;1.113500;0
+";
+
+    fn read(text: &str) -> Result<DataFrame, String> {
+        read_m1(text.as_bytes())
+    }
+
+    // The string columns parsed by Polars before the typed reader, see `benches/m1_reader.rs`
+    fn parse_as_strings(text: &str) -> DataFrame {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        let schema = Schema::from_iter((1..=6).map(|i| Field::new(format!("column_{}", i).into(), DataType::String)));
+        let number = |name: &str, dtype: DataType| col(name).str().replace_all(lit(" "), lit(""), false).cast(dtype);
+
+        LazyCsvReader::new(file.path())
+            .with_separator(b';')
+            .with_has_header(false)
+            .with_schema(Some(Arc::new(schema)))
+            .finish()
+            .unwrap()
+            .select([
+                col("column_1")
+                    .str()
+                    .to_datetime(
+                        Some(TimeUnit::Microseconds),
+                        None,
+                        StrptimeOptions {
+                            format: Some("%Y%m%d %H%M%S".into()),
+                            ..Default::default()
+                        },
+                        lit("raise"),
+                    )
+                    .alias("datetime"),
+                number("column_2", DataType::Float64).alias("open"),
+                number("column_3", DataType::Float64).alias("high"),
+                number("column_4", DataType::Float64).alias("low"),
+                number("column_5", DataType::Float64).alias("close"),
+                number("column_6", DataType::Int64).alias("volume"),
+            ])
+            .collect()
+            .unwrap()
+    }
+
+    #[test]
+    fn the_bars_match_the_string_parsing() {
+        let bars = read(LINES).unwrap();
+
+        assert_eq!(bars.height(), 4);
+        assert!(bars.equals(&parse_as_strings(LINES)));
+    }
+
+    #[test]
+    fn crlf_line_endings_and_blank_lines_are_read() {
+        let crlf = LINES.replace('\n', "\r\n") + "\r\n";
+
+        assert!(read(&crlf).unwrap().equals(&read(LINES).unwrap()));
+    }
+
+    #[test]
+    fn a_malformed_line_is_refused_with_its_number() {
+        let cases = [
+            ("20200230 000000;1.1;1.1;1.1;1.1;0", "invalid date"),
+            ("2020022 0000000;1.1;1.1;1.1;1.1;0", "expected a datetime"),
+            ("20200228 240000;1.1;1.1;1.1;1.1;0", "invalid time"),
+            ("20200228 235800;1.1;1.1;1.1;1.1;0;0", "too many fields"),
+            ("20200228 235800;1.1;1.1;1.1;1.1", "invalid volume"),
+            ("20200228 235800;1.1;1.1;1.1", "invalid close price"),
+            ("20200228 2358", "expected a datetime"),
+            ("20200228 235800;1.1;1.1;NaN;1.1;0", "invalid low price"),
+        ];
+
+        for (line, reason) in cases {
+            let error = read(&format!("{}{}\n", LINES, line)).unwrap_err();
+            assert!(error.starts_with("Invalid M1 bar on line 5: "), "{}", error);
+            assert!(error.contains(reason), "{}", error);
+        }
+    }
+
+    #[test]
+    fn a_truncated_last_line_is_refused() {
+        let truncated = &LINES[..LINES.len() - 6];
+
+        assert!(read(truncated).unwrap_err().contains("line 4: invalid volume"));
+    }
+}
@@ -0,0 +1,42 @@
+use crate::data::output::{PendingFile, WriteMode};
+
+use polars::prelude::*;
+use std::{
+    fs::remove_file,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+// `<data_dir>/<PAIR>_<name>.csv`
+pub fn report_path(data_dir: &Path, pair: &str, name: &str) -> PathBuf {
+    data_dir.join(format!("{}_{}.csv", pair, name))
+}
+
+// Write a report next to the data as `<PAIR>_<name>.csv`
+// A report only covers the new data, so merging replaces it like overwriting
+pub fn save_report(df: &mut DataFrame, data_dir: &Path, pair: &str, name: &str, write_mode: WriteMode) -> Result<PathBuf, String> {
+    let (pending, file) = PendingFile::create(report_path(data_dir, pair, name), write_mode)?;
+    let mut file = BufWriter::new(file);
+
+    CsvWriter::new(&mut file)
+        .finish(df)
+        .map_err(|e| format!("Failed to write {} file: {}", name, e))?;
+    file.flush().map_err(|e| format!("Failed to write {} file: {}", name, e))?;
+    drop(file);
+
+    pending.commit()
+}
+
+// Remove the report left by a previous run
+// so it is not taken for the report of the new data
+pub fn remove_report(data_dir: &Path, pair: &str, name: &str, write_mode: WriteMode) -> Result<(), String> {
+    let file_path = report_path(data_dir, pair, name);
+    if !file_path.exists() {
+        return Ok(());
+    }
+    if write_mode == WriteMode::ErrorIfExists {
+        return Err(format!("{} already exists, choose to overwrite or merge it", file_path.display()));
+    }
+
+    remove_file(&file_path).map_err(|e| format!("Failed to remove file {}: {}", file_path.display(), e))
+}
@@ -0,0 +1,331 @@
+use crate::PAIRS;
+use crate::data::{
+    handler::download_data,
+    options::{Backend, CancelToken, DownloadOptions},
+    naming::Timeframe,
+    output::{OutputFormat, OutputOptions},
+    pairs::build_pairs,
+    summary::DownloadSummary,
+};
+use crate::utils::dates::{DatePreset, check_range, max_available_date};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::{
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    sync::mpsc::{Sender, channel},
+    task::JoinHandle,
+};
+
+// Everything needed to download one or more symbols
+// Built step by step, then checked against the instrument registry by `start`
+//
+//     let summaries = DownloadRequest::new()
+//         .symbols(["EUR/USD", "GBPUSD"])
+//         .range(from_date, to_date)
+//         .format(OutputFormat::Parquet)
+//         .output("data")
+//         .start()
+//         .await?;
+#[derive(Clone, Debug, Default)]
+pub struct DownloadRequest {
+    symbols: Vec<String>,
+    // The full history of each symbol when `None`
+    range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    format: OutputFormat,
+    data_dir: Option<PathBuf>,
+    // Set at once with `options` and `output_options`
+    options: DownloadOptions,
+    // Set one by one, they win over `options` and `output_options` whatever the order
+    timeframe: Option<Timeframe>,
+    timezone: Option<Tz>,
+    concurrency: Option<usize>,
+    backend: Option<Backend>,
+    cache_dir: Option<PathBuf>,
+    progress: Option<Sender<usize>>,
+}
+
+impl DownloadRequest {
+    pub fn new() -> Self {
+        DownloadRequest::default()
+    }
+
+    // `EUR/USD` and `eurusd` both name the `EURUSD` pair
+    pub fn symbol(mut self, symbol: impl AsRef<str>) -> Self {
+        self.symbols.push(symbol.as_ref().replace('/', "").trim().to_uppercase());
+        self
+    }
+
+    pub fn symbols<S: AsRef<str>>(self, symbols: impl IntoIterator<Item = S>) -> Self {
+        symbols.into_iter().fold(self, |request, symbol| request.symbol(symbol))
+    }
+
+    pub fn range(mut self, from_date: DateTime<Utc>, to_date: DateTime<Utc>) -> Self {
+        self.range = Some((from_date, to_date));
+        self
+    }
+
+    // Written in the `{timeframe}` placeholder of the file names
+    pub fn timeframe(mut self, timeframe: Timeframe) -> Self {
+        self.timeframe = Some(timeframe);
+        self
+    }
+
+    // Timezone of the datetimes written, see `OutputOptions::timezone`
+    pub fn timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = Some(timezone);
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    // Directory the files are written to
+    pub fn output(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(data_dir.into());
+        self
+    }
+
+    // Write mode, file names, split and format settings
+    // The timeframe and the timezone set with their own setters are kept
+    pub fn output_options(mut self, output: OutputOptions) -> Self {
+        self.options.output = output;
+        self
+    }
+
+    // Every processing option at once, replacing the output options set before
+    // The timeframe, timezone, concurrency, backend and cache set with their own setters are kept
+    pub fn options(mut self, options: DownloadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Number of years downloaded in parallel for each symbol
+    pub fn concurrency(mut self, tasks: usize) -> Self {
+        self.concurrency = Some(tasks);
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    // Directory keeping the downloaded archives for the next runs
+    pub fn cache(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    // Receives the progress of each symbol in percent, then 0 once it is saved
+    pub fn progress(mut self, tx: Sender<usize>) -> Self {
+        self.progress = Some(tx);
+        self
+    }
+
+    // The options with the ones set one by one applied over them
+    pub fn download_options(&self) -> DownloadOptions {
+        let mut options = self.options.clone();
+        if let Some(timeframe) = self.timeframe {
+            options.output.timeframe = timeframe;
+        }
+        if let Some(timezone) = self.timezone {
+            options.output.timezone = Some(timezone);
+        }
+        if let Some(backend) = self.backend {
+            options.backend = backend;
+        }
+        options.concurrency = self.concurrency.or(options.concurrency);
+        options.cache_dir = self.cache_dir.clone().or(options.cache_dir);
+
+        options
+    }
+
+    // Check the request against the instrument registry
+    // Returns each symbol with the range to download
+    pub async fn validate(&self) -> Result<Vec<(String, DateTime<Utc>, DateTime<Utc>)>, String> {
+        let options = self.download_options();
+        if self.symbols.is_empty() {
+            return Err("No symbol to download".into());
+        }
+        if self.data_dir.is_none() {
+            return Err("No output directory, set one with `output`".into());
+        }
+        if options.concurrency == Some(0) {
+            return Err("The concurrency must be at least 1".into());
+        }
+        let Backend::ChromeDriver { ports: (from_port, to_port) } = options.backend;
+        if from_port >= to_port {
+            return Err(format!("Invalid chromedriver port range {}..{}", from_port, to_port));
+        }
+
+        // Library users may not have filled the registry
+        if PAIRS.lock().await.is_empty() {
+            build_pairs().await;
+        }
+
+        let max_date = max_available_date();
+        let pairs = PAIRS.lock().await;
+        let mut jobs: Vec<(String, DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+        for symbol in &self.symbols {
+            if jobs.iter().any(|(pair, _, _)| pair == symbol) {
+                return Err(format!("{} is requested twice", symbol));
+            }
+            let min_date = *pairs.get(symbol).ok_or_else(|| format!("Unknown symbol '{}', HistData has no such instrument", symbol))?;
+
+            let (from_date, to_date) = match self.range {
+                Some((from_date, to_date)) => {
+                    check_range(from_date, to_date, min_date, max_date).map_err(|e| format!("{}: {}", symbol, e))?;
+                    (from_date, to_date)
+                }
+                None => DatePreset::FullHistory.resolve(min_date, max_date)?,
+            };
+            jobs.push((symbol.clone(), from_date, to_date));
+        }
+
+        Ok(jobs)
+    }
+
+    // Validate the request and download the symbols one after the other
+    // Must be called within a Tokio runtime
+    pub fn start(self) -> DownloadHandle {
+        let options = self.download_options();
+        let cancel = options.cancel.clone();
+
+        let task = tokio::spawn(async move {
+            let jobs = self.validate().await?;
+            let data_dir = self.data_dir.unwrap();
+
+            // Nothing shows the progress
+            let progress = self.progress.unwrap_or_else(|| {
+                let (tx, mut rx) = channel(100);
+                tokio::spawn(async move { while rx.recv().await.is_some() {} });
+                tx
+            });
+
+            let mut summaries = Vec::new();
+            for (symbol, from_date, to_date) in jobs {
+                if options.cancel.is_cancelled() {
+                    return Err("The download was cancelled".to_string());
+                }
+                summaries.push(download_data(symbol, from_date, to_date, data_dir.clone(), self.format, options.clone(), progress.clone()).await?);
+            }
+
+            Ok(summaries)
+        });
+
+        DownloadHandle { task, cancel }
+    }
+}
+
+// A started download
+// Awaiting it gives the summary of each symbol, in the order requested
+pub struct DownloadHandle {
+    task: JoinHandle<Result<Vec<DownloadSummary>, String>>,
+    cancel: CancelToken,
+}
+
+impl DownloadHandle {
+    // Stop the download, awaiting the handle then gives the cancel error
+    // The workers finish the year in progress and the staging files are removed
+    // once they are done, unless the bars are already being saved
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl Future for DownloadHandle {
+    type Output = Result<Vec<DownloadSummary>, String>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.task).poll(cx).map(|result| match result {
+            Ok(result) => result,
+            Err(e) if e.is_cancelled() => Err("The download was cancelled".to_string()),
+            Err(e) => Err(format!("The download task failed: {}", e)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::output::WriteMode;
+    use ::zip::{ZipWriter, write::SimpleFileOptions};
+    use chrono::TimeZone;
+    use std::{fs::File, io::Write, path::Path};
+    use tempfile::tempdir;
+
+    // A yearly archive in the cache, so nothing is downloaded
+    fn cache_archive(cache_dir: &Path, year: usize) {
+        let mut zip = ZipWriter::new(File::create(cache_dir.join(format!("HISTDATA_COM_ASCII_EURUSD_M1{}.zip", year))).unwrap());
+        zip.start_file(format!("DAT_ASCII_EURUSD_M1_{}.csv", year), SimpleFileOptions::default()).unwrap();
+        for day in 2..5 {
+            writeln!(zip, "{}01{:02} 120000;1.121200;1.121300;1.121100;1.121270;0", year, day).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_download_waits_for_its_workers() {
+        let (data_dir, cache_dir) = (tempdir().unwrap(), tempdir().unwrap());
+        cache_archive(cache_dir.path(), 2019);
+        cache_archive(cache_dir.path(), 2020);
+
+        // A single worker blocked on the progress until the download is cancelled
+        let (tx, mut rx) = channel(1);
+        let handle = DownloadRequest::new()
+            .symbol("EURUSD")
+            .range(Utc.with_ymd_and_hms(2019, 1, 2, 0, 0, 0).unwrap(), Utc.with_ymd_and_hms(2020, 12, 31, 0, 0, 0).unwrap())
+            .output(data_dir.path())
+            .cache(cache_dir.path())
+            .concurrency(1)
+            .progress(tx)
+            .start();
+        assert!(rx.recv().await.is_some());
+        handle.cancel();
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+
+        assert_eq!(handle.await.unwrap_err(), "The download was cancelled");
+        // Neither the staging directory nor an output file is left behind
+        assert_eq!(std::fs::read_dir(data_dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_request_is_checked_before_it_starts() {
+        let error = |request: DownloadRequest| async move { request.validate().await.unwrap_err() };
+
+        assert_eq!(error(DownloadRequest::new().output("data")).await, "No symbol to download");
+        assert_eq!(error(DownloadRequest::new().symbol("EURUSD")).await, "No output directory, set one with `output`");
+        assert!(error(DownloadRequest::new().symbol("EUR/XYZ").output("data")).await.contains("Unknown symbol 'EURXYZ'"));
+        assert_eq!(error(DownloadRequest::new().symbols(["EURUSD", "eur/usd"]).output("data")).await, "EURUSD is requested twice");
+        assert_eq!(error(DownloadRequest::new().symbol("EURUSD").output("data").concurrency(0)).await, "The concurrency must be at least 1");
+    }
+
+    #[test]
+    fn the_options_set_one_by_one_are_kept() {
+        let options = DownloadOptions { concurrency: Some(4), cache_dir: Some("archives".into()), ..Default::default() };
+        let output = OutputOptions { write_mode: WriteMode::Overwrite, timezone: Some(Tz::Europe__London), ..Default::default() };
+
+        let options = DownloadRequest::new()
+            .concurrency(2)
+            .timezone(Tz::Asia__Tokyo)
+            .options(options)
+            .output_options(output)
+            .download_options();
+        assert_eq!(options.concurrency, Some(2));
+        assert_eq!(options.cache_dir, Some(PathBuf::from("archives")));
+        assert_eq!(options.output.write_mode, WriteMode::Overwrite);
+        assert_eq!(options.output.timezone, Some(Tz::Asia__Tokyo));
+    }
+}
@@ -0,0 +1,213 @@
+use chrono::{DateTime, NaiveDateTime, NaiveTime, TimeZone};
+use chrono_tz::{America::New_York, Asia::Tokyo, Australia::Sydney, Europe::London, Tz};
+use polars::prelude::*;
+
+// A trading session, either the business hours of a financial center
+// or the overlap of several sessions
+// The hours are local to each center so the boundaries follow its DST
+#[derive(Clone, Debug, PartialEq)]
+pub enum TradingSession {
+    Local {
+        name: String,
+        timezone: Tz,
+        start: NaiveTime,
+        end: NaiveTime,
+    },
+    Overlap {
+        name: String,
+        sessions: Vec<TradingSession>,
+    },
+}
+
+impl TradingSession {
+    // A session from `start` to `end` in the local time of `timezone`
+    // If `end` is before `start` the session goes through midnight
+    pub fn custom(name: &str, timezone: Tz, start: NaiveTime, end: NaiveTime) -> Self {
+        TradingSession::Local { name: name.to_string(), timezone, start, end }
+    }
+
+    pub fn overlap(name: &str, sessions: Vec<TradingSession>) -> Self {
+        TradingSession::Overlap { name: name.to_string(), sessions }
+    }
+
+    pub fn sydney() -> Self {
+        Self::custom("sydney", Sydney, hour(7), hour(16))
+    }
+
+    pub fn tokyo() -> Self {
+        Self::custom("tokyo", Tokyo, hour(9), hour(18))
+    }
+
+    pub fn london() -> Self {
+        Self::custom("london", London, hour(8), hour(17))
+    }
+
+    pub fn new_york() -> Self {
+        Self::custom("new_york", New_York, hour(8), hour(17))
+    }
+
+    pub fn sydney_tokyo() -> Self {
+        Self::overlap("sydney_tokyo", vec![Self::sydney(), Self::tokyo()])
+    }
+
+    pub fn tokyo_london() -> Self {
+        Self::overlap("tokyo_london", vec![Self::tokyo(), Self::london()])
+    }
+
+    pub fn london_new_york() -> Self {
+        Self::overlap("london_new_york", vec![Self::london(), Self::new_york()])
+    }
+
+    // The four main sessions
+    pub fn main_sessions() -> Vec<TradingSession> {
+        vec![Self::sydney(), Self::tokyo(), Self::london(), Self::new_york()]
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            TradingSession::Local { name, .. } | TradingSession::Overlap { name, .. } => name,
+        }
+    }
+
+    // Whether a UTC time is inside the session
+    // The start is inclusive and the end exclusive
+    pub fn contains(&self, datetime: NaiveDateTime) -> bool {
+        match self {
+            TradingSession::Local { timezone, start, end, .. } => {
+                let time = timezone.from_utc_datetime(&datetime).time();
+
+                if start <= end {
+                    time >= *start && time < *end
+                } else {
+                    time >= *start || time < *end
+                }
+            }
+            TradingSession::Overlap { sessions, .. } => sessions.iter().all(|session| session.contains(datetime)),
+        }
+    }
+}
+
+fn hour(hour: u32) -> NaiveTime {
+    NaiveTime::from_hms_opt(hour, 0, 0).unwrap()
+}
+
+fn datetimes(df: &DataFrame) -> Result<Vec<Option<NaiveDateTime>>, String> {
+    Ok(df.column("datetime")
+        .and_then(|c| c.datetime())
+        .map_err(|e| format!("Failed to read datetimes: {}", e))?
+        .into_iter()
+        .map(|micros| micros.and_then(DateTime::from_timestamp_micros).map(|dt| dt.naive_utc()))
+        .collect())
+}
+
+// Only keep the bars inside at least one of the sessions
+pub fn filter_sessions(df: &DataFrame, sessions: &[TradingSession]) -> Result<DataFrame, String> {
+    let mask: BooleanChunked = datetimes(df)?
+        .into_iter()
+        .map(|datetime| datetime.is_some_and(|datetime| sessions.iter().any(|session| session.contains(datetime))))
+        .collect();
+
+    df.filter(&mask).map_err(|e| format!("Failed to filter sessions: {}", e))
+}
+
+// Add a `session` column with the sessions of each bar joined by `+`
+// The column is null outside of every session
+pub fn tag_sessions(df: &DataFrame, sessions: &[TradingSession]) -> Result<DataFrame, String> {
+    let tags: Vec<Option<String>> = datetimes(df)?
+        .into_iter()
+        .map(|datetime| {
+            let datetime = datetime?;
+            let names = sessions.iter()
+                .filter(|session| session.contains(datetime))
+                .map(|session| session.name())
+                .collect::<Vec<&str>>();
+
+            (!names.is_empty()).then(|| names.join("+"))
+        })
+        .collect();
+
+    let mut df = df.clone();
+    df.with_column(Column::new("session".into(), tags))
+        .map_err(|e| format!("Failed to tag sessions: {}", e))?;
+
+    Ok(df)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(datetime: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    // The session starts at `start` and ends at `end`, in UTC
+    fn assert_bounds(session: &TradingSession, start: &str, end: &str) {
+        let (start, end) = (utc(start), utc(end));
+        let minute = chrono::Duration::minutes(1);
+
+        assert!(!session.contains(start - minute), "{} should start at {}", session.name(), start);
+        assert!(session.contains(start), "{} should start at {}", session.name(), start);
+        assert!(session.contains(end - minute), "{} should end at {}", session.name(), end);
+        assert!(!session.contains(end), "{} should end at {}", session.name(), end);
+    }
+
+    fn bars(datetimes: &[&str]) -> DataFrame {
+        let micros = datetimes.iter().map(|datetime| utc(datetime).and_utc().timestamp_micros()).collect::<Vec<i64>>();
+
+        DataFrame::new(vec![Column::new("datetime".into(), micros).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap()]).unwrap()
+    }
+
+    #[test]
+    fn london_and_new_york_follow_their_own_daylight_saving() {
+        let (london, new_york, overlap) = (TradingSession::london(), TradingSession::new_york(), TradingSession::london_new_york());
+
+        // Both on winter time
+        assert_bounds(&london, "2020-03-02 08:00", "2020-03-02 17:00");
+        assert_bounds(&new_york, "2020-03-02 13:00", "2020-03-02 22:00");
+        assert_bounds(&overlap, "2020-03-02 13:00", "2020-03-02 17:00");
+
+        // New York moves on March 8, London on March 29
+        assert_bounds(&new_york, "2020-03-10 12:00", "2020-03-10 21:00");
+        assert_bounds(&overlap, "2020-03-10 12:00", "2020-03-10 17:00");
+        assert_bounds(&london, "2020-04-01 07:00", "2020-04-01 16:00");
+        assert_bounds(&overlap, "2020-04-01 12:00", "2020-04-01 16:00");
+
+        // London moves back on October 25, New York on November 1
+        assert_bounds(&london, "2020-10-28 08:00", "2020-10-28 17:00");
+        assert_bounds(&overlap, "2020-10-28 12:00", "2020-10-28 17:00");
+        assert_bounds(&overlap, "2020-11-04 13:00", "2020-11-04 17:00");
+    }
+
+    #[test]
+    fn sydney_runs_past_utc_midnight() {
+        let sydney = TradingSession::sydney();
+
+        // UTC+11 in January, UTC+10 in July
+        assert_bounds(&sydney, "2020-01-06 20:00", "2020-01-07 05:00");
+        assert!(sydney.contains(utc("2020-01-07 00:00")));
+        assert_bounds(&sydney, "2020-07-06 21:00", "2020-07-07 06:00");
+        assert_bounds(&TradingSession::sydney_tokyo(), "2020-07-07 00:00", "2020-07-07 06:00");
+    }
+
+    #[test]
+    fn a_custom_session_can_go_through_midnight() {
+        let night = TradingSession::custom("night", Tz::UTC, hour(22), hour(2));
+
+        assert_bounds(&night, "2020-01-06 22:00", "2020-01-07 02:00");
+        assert!(!night.contains(utc("2020-01-07 12:00")));
+    }
+
+    #[test]
+    fn the_bars_are_filtered_and_tagged() {
+        let df = bars(&["2020-01-06 05:30", "2020-01-06 08:30", "2020-01-06 13:30", "2020-01-06 17:30"]);
+        let sessions = [TradingSession::london(), TradingSession::new_york(), TradingSession::london_new_york()];
+
+        let tagged = tag_sessions(&df, &sessions).unwrap();
+        let tags = tagged.column("session").unwrap().str().unwrap().into_iter().collect::<Vec<Option<&str>>>();
+        assert_eq!(tags, [None, Some("london"), Some("london+new_york+london_new_york"), Some("new_york")]);
+
+        assert_eq!(filter_sessions(&df, &sessions).unwrap().height(), 3);
+        assert!(filter_sessions(&df, &[TradingSession::london_new_york()]).unwrap().equals(&df.slice(2, 1)));
+    }
+}
@@ -0,0 +1,163 @@
+use polars::prelude::*;
+use rusqlite::{Connection, OptionalExtension, params, params_from_iter, types::Value};
+use std::path::Path;
+
+// How the bars are laid out in the database
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SqliteLayout {
+    // One table per instrument, named after it
+    #[default]
+    TablePerSymbol,
+    // A single table with a `symbol` column
+    SingleTable,
+}
+
+#[derive(Clone, Debug)]
+pub struct SqliteOptions {
+    // Name of the database file inside the destination directory
+    pub database: String,
+    pub layout: SqliteLayout,
+    // Name of the table used by the single table layout
+    pub table: String,
+}
+
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        SqliteOptions {
+            database: "histdata.sqlite".to_string(),
+            layout: SqliteLayout::default(),
+            table: "bars".to_string(),
+        }
+    }
+}
+
+// Write the bars into a SQLite database
+// The timestamps are stored as UTC Unix seconds
+// and the bars already in the database for the same timestamps are replaced
+pub fn write_sqlite(df: &DataFrame, path: &Path, pair: &str, options: &SqliteOptions, metadata: &[(String, String)]) -> Result<(), String> {
+    let sql_error = |e: rusqlite::Error| format!("Failed to write SQLite database {}: {}", path.display(), e);
+
+    let mut connection = Connection::open(path).map_err(sql_error)?;
+    let transaction = connection.transaction().map_err(sql_error)?;
+
+    // The key is the timestamp, plus the symbol in the single table layout
+    let (table, mut columns, key) = match options.layout {
+        SqliteLayout::TablePerSymbol => (pair.to_string(), Vec::new(), vec!["datetime"]),
+        SqliteLayout::SingleTable => (options.table.clone(), vec![("symbol".to_string(), "TEXT NOT NULL")], vec!["symbol", "datetime"]),
+    };
+    for column in df.get_columns() {
+        let sql_type = if column.name() == "datetime" { "INTEGER NOT NULL" } else { sql_type(column.dtype()) };
+        columns.push((column.name().to_string(), sql_type));
+    }
+
+    let definitions = columns.iter()
+        .map(|(name, sql_type)| format!("{} {}", quote(name), sql_type))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let key_columns = key.iter().map(|name| quote(name)).collect::<Vec<String>>().join(", ");
+    transaction.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {table} ({definitions}, PRIMARY KEY ({key_columns}));
+         CREATE TABLE IF NOT EXISTS \"metadata\" (\"table_name\" TEXT NOT NULL, \"symbol\" TEXT NOT NULL, \"key\" TEXT NOT NULL, \"value\" TEXT, PRIMARY KEY (\"table_name\", \"symbol\", \"key\"));",
+        table = quote(&table),
+    )).map_err(sql_error)?;
+
+    // The bars already in the table must store their prices like the new ones
+    let stored: Option<String> = transaction.query_row(
+        "SELECT \"value\" FROM \"metadata\" WHERE \"table_name\" = ?1 AND \"symbol\" = ?2 AND \"key\" = 'price_storage'",
+        params![table, pair],
+        |row| row.get(0),
+    ).optional().map_err(sql_error)?;
+    let storage = metadata.iter().find(|(key, _)| key == "price_storage").map(|(_, value)| value);
+    if let (Some(stored), Some(storage)) = (&stored, storage) && stored != storage {
+        return Err(format!("The {} prices in {} are stored as {}, not {}", pair, path.display(), stored, storage));
+    }
+
+    // The primary key already indexes the timestamps of a table per symbol
+    // The single table also needs them indexed across symbols
+    if options.layout == SqliteLayout::SingleTable {
+        transaction.execute_batch(&format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} (\"datetime\");",
+            quote(&format!("{}_datetime", table)),
+            quote(&table),
+        )).map_err(sql_error)?;
+    }
+
+    // Upsert the bars
+    let names = columns.iter().map(|(name, _)| quote(name)).collect::<Vec<String>>();
+    let updates = columns.iter()
+        .filter(|(name, _)| !key.contains(&name.as_str()))
+        .map(|(name, _)| format!("{name} = excluded.{name}", name = quote(name)))
+        .collect::<Vec<String>>();
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT ({}) DO UPDATE SET {}",
+        quote(&table),
+        names.join(", "),
+        vec!["?"; names.len()].join(", "),
+        key_columns,
+        updates.join(", "),
+    );
+
+    {
+        let mut statement = transaction.prepare(&sql).map_err(sql_error)?;
+        // Single chunks make the row access cheap
+        let mut df = df.clone();
+        df.as_single_chunk_par();
+
+        for i in 0..df.height() {
+            let mut row = Vec::with_capacity(names.len());
+            if options.layout == SqliteLayout::SingleTable {
+                row.push(Value::Text(pair.to_string()));
+            }
+            for column in df.get_columns() {
+                let value = column.get(i).map_err(|e| format!("Failed to read row {}: {}", i, e))?;
+                row.push(sql_value(value));
+            }
+
+            statement.execute(params_from_iter(row)).map_err(sql_error)?;
+        }
+    }
+
+    // Describe the data so the database is self-describing
+    let datetime_unit = ("datetime_unit".to_string(), "unix_seconds".to_string());
+    for (key, value) in metadata.iter().chain([&datetime_unit]) {
+        transaction.execute(
+            "INSERT INTO \"metadata\" (\"table_name\", \"symbol\", \"key\", \"value\") VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (\"table_name\", \"symbol\", \"key\") DO UPDATE SET \"value\" = excluded.\"value\"",
+            params![table, pair, key, value],
+        ).map_err(sql_error)?;
+    }
+
+    transaction.commit().map_err(sql_error)
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sql_type(dtype: &DataType) -> &'static str {
+    match dtype {
+        DataType::Float32 | DataType::Float64 => "REAL",
+        DataType::String => "TEXT",
+        _ => "INTEGER",
+    }
+}
+
+fn sql_value(value: AnyValue) -> Value {
+    match value {
+        AnyValue::Null => Value::Null,
+        AnyValue::Boolean(b) => Value::Integer(b as i64),
+        AnyValue::Float32(f) => Value::Real(f as f64),
+        AnyValue::Float64(f) => Value::Real(f),
+        AnyValue::String(s) => Value::Text(s.to_string()),
+        AnyValue::StringOwned(s) => Value::Text(s.to_string()),
+        AnyValue::Datetime(v, unit, _) => Value::Integer(match unit {
+            TimeUnit::Nanoseconds => v.div_euclid(1_000_000_000),
+            TimeUnit::Microseconds => v.div_euclid(1_000_000),
+            TimeUnit::Milliseconds => v.div_euclid(1_000),
+        }),
+        other => match other.extract::<i64>() {
+            Some(v) => Value::Integer(v),
+            None => Value::Text(other.to_string()),
+        },
+    }
+}
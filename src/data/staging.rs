@@ -0,0 +1,81 @@
+use polars::prelude::*;
+use std::{
+    fs::{File, create_dir_all, remove_dir_all},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Directory holding the bars between the steps of a download
+// so only about one year of bars is in memory at a time
+// It is removed with everything inside when dropped
+pub struct StagingDir {
+    path: PathBuf,
+}
+
+impl StagingDir {
+    // `<data_dir>/.<PAIR>_staging_<PID>_<N>`, emptied if a previous run left it behind
+    // The process id and a counter keep the concurrent downloads of a pair apart
+    pub fn create(data_dir: &Path, pair: &str) -> Result<Self, String> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = data_dir.join(format!(".{}_staging_{}_{}", pair, process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        if path.exists() {
+            remove_dir_all(&path).map_err(|e| format!("Failed to remove directory {}: {}", path.display(), e))?;
+        }
+        create_dir_all(&path).map_err(|e| format!("Failed to create directory {}: {}", path.display(), e))?;
+
+        Ok(StagingDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.path);
+    }
+}
+
+// Write the bars to a staging file
+// lz4 is quick to write and read back, the file only lives during the download
+pub fn stage(df: &mut DataFrame, path: &Path) -> Result<PathBuf, String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create file {}: {}", path.display(), e))?;
+    ParquetWriter::new(file)
+        .with_compression(ParquetCompression::Lz4Raw)
+        .finish(df)
+        .map_err(|e| format!("Failed to write staging file {}: {}", path.display(), e))?;
+
+    Ok(path.to_path_buf())
+}
+
+pub fn scan_staged(path: &Path) -> Result<LazyFrame, String> {
+    LazyFrame::scan_parquet(path, ScanArgsParquet::default())
+        .map_err(|e| format!("Failed to scan staging file {}: {}", path.display(), e))
+}
+
+pub fn read_staged(path: &Path) -> Result<DataFrame, String> {
+    scan_staged(path)?
+        .collect()
+        .map_err(|e| format!("Failed to read staging file {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn each_download_gets_its_own_directory() {
+        let dir = tempdir().unwrap();
+        let first = StagingDir::create(dir.path(), "EURUSD").unwrap();
+        let second = StagingDir::create(dir.path(), "EURUSD").unwrap();
+        assert_ne!(first.path(), second.path());
+
+        let path = second.path().to_path_buf();
+        drop(second);
+        assert!(!path.exists());
+        assert!(first.path().exists());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::data::{
+    bars::{Bar, iter_bars},
+    handler::{Workers, YearPipeline, spawn_downloads, years_to_download},
+    options::DownloadOptions,
+    staging::StagingDir,
+    volume::{VolumePolicy, is_zero_volume},
+};
+
+use chrono::{DateTime, Utc};
+use futures::{SinkExt, Stream, StreamExt, channel::mpsc, stream};
+use polars::prelude::*;
+use std::{env::temp_dir, pin::pin};
+
+// The symbol, the period (the year) and the processed bars of that period
+pub type PeriodBars = (String, String, DataFrame);
+
+// Download the bars of a pair and yield each year as soon as it is processed
+// The years come in order, the last day of a year coming with the next one
+// so the bars repeated at the boundary are removed
+// A year that failed to download is yielded as an error and the others keep coming
+// Only staging files are written, in the temporary directory, and the volume
+// column is always kept since the whole range is needed to know it is all zeros
+// Counting the ticks needs the volume `tick_dir`, there is no data directory to default to
+// Dropping the stream stops the workers before their next year,
+// and the staging files are removed once they are done
+pub fn download_stream(pair: String, from_date: DateTime<Utc>, to_date: DateTime<Utc>, options: DownloadOptions) -> impl Stream<Item = Result<PeriodBars, String>> {
+    // A single slot, so the downloads don't run far ahead of the consumer
+    let (mut sender, receiver) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        if let Err(e) = stream_years(&pair, from_date, to_date, &options, &mut sender).await {
+            let _ = sender.send(Err(e)).await;
+        }
+    });
+
+    receiver
+}
+
+// Same as `download_stream`, one `Bar` at a time
+// The empty minutes of a gap filling without prices are yielded as errors
+pub fn download_bars(pair: String, from_date: DateTime<Utc>, to_date: DateTime<Utc>, options: DownloadOptions) -> impl Stream<Item = Result<Bar, String>> {
+    download_stream(pair, from_date, to_date, options).flat_map(|period| match period.and_then(|(_, _, df)| iter_bars(&df)) {
+        Ok(bars) => stream::iter(bars).left_stream(),
+        Err(e) => stream::once(async move { Err(e) }).right_stream(),
+    })
+}
+
+// Download the bars of a pair into a single DataFrame, without writing anything
+// Fails if any year fails, rather than returning a partial range
+pub async fn download_dataframe(pair: String, from_date: DateTime<Utc>, to_date: DateTime<Utc>, options: DownloadOptions) -> Result<DataFrame, String> {
+    let drop_zero_volume = options.volume.policy == VolumePolicy::DropIfZero;
+    let mut stream = pin!(download_stream(pair, from_date, to_date, options));
+
+    let mut bars: Option<DataFrame> = None;
+    while let Some(item) = stream.next().await {
+        let (_, _, df) = item?;
+        match bars.as_mut() {
+            Some(bars) => {
+                bars.vstack_mut(&df).map_err(|e| format!("Failed to merge years: {}", e))?;
+            }
+            None => bars = Some(df),
+        }
+    }
+
+    let mut bars = bars.ok_or("No bars were downloaded")?;
+    bars.as_single_chunk_par();
+    if drop_zero_volume && is_zero_volume(&bars)? {
+        bars = bars.drop("volume").map_err(|e| format!("Failed to drop volume: {}", e))?;
+    }
+
+    Ok(bars)
+}
+
+async fn stream_years(
+    pair: &str,
+    from_date: DateTime<Utc>,
+    to_date: DateTime<Utc>,
+    options: &DownloadOptions,
+    sender: &mut mpsc::Sender<Result<PeriodBars, String>>,
+) -> Result<(), String> {
+    let years = years_to_download(pair, from_date, to_date).await;
+    let tick_dir = options.volume.tick_archive_dir(None)?;
+    let staging_dir = StagingDir::create(&temp_dir(), pair)?;
+
+    // Nothing shows the progress
+    let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+    tokio::spawn(async move { while rx.recv().await.is_some() {} });
+    let mut workers = spawn_downloads(pair, &years, staging_dir.path(), options, tx);
+
+    let mut pipeline = YearPipeline::new(pair, &years, (from_date, to_date), &tick_dir, options);
+    let result = send_years(pair, &mut pipeline, &mut workers, sender).await;
+
+    // The workers are stopped when the stream is dropped or fails,
+    // then waited for before the staging directory is removed
+    workers.stop().await;
+    drop(staging_dir);
+    result?;
+
+    // In strict mode any issue fails the stream once every year is out
+    let (summary, _) = pipeline.into_summary();
+    if options.validation.enabled && options.validation.strict && !summary.issues.is_empty() {
+        return Err(format!("Validation found {} issues", summary.issues.len()));
+    }
+
+    Ok(())
+}
+
+async fn send_years(
+    pair: &str,
+    pipeline: &mut YearPipeline<'_>,
+    workers: &mut Workers,
+    sender: &mut mpsc::Sender<Result<PeriodBars, String>>,
+) -> Result<(), String> {
+    let dropped = |_| "The stream was dropped".to_string();
+    while let Some(staged) = workers.recv().await {
+        match staged {
+            Ok(staged) => {
+                for (year, df) in pipeline.push(staged)? {
+                    sender.send(Ok((pair.to_string(), year.to_string(), df))).await.map_err(dropped)?;
+                }
+            }
+            Err(e) => sender.send(Err(e)).await.map_err(dropped)?,
+        }
+    }
+    for (year, df) in pipeline.finish()? {
+        sender.send(Ok((pair.to_string(), year.to_string(), df))).await.map_err(dropped)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::volume::VolumeOptions;
+    use ::zip::{ZipWriter, write::SimpleFileOptions};
+    use chrono::TimeZone;
+    use std::{fs::File, io::Write, process, time::Duration};
+    use tempfile::{TempDir, tempdir};
+
+    // Yearly archives in the cache, so nothing is downloaded
+    // Three bars each year, at noon in the source timezone
+    fn cached_options(pair: &str, years: &[usize]) -> (TempDir, DownloadOptions) {
+        let cache_dir = tempdir().unwrap();
+        for year in years {
+            let mut zip = ZipWriter::new(File::create(cache_dir.path().join(format!("HISTDATA_COM_ASCII_{}_M1{}.zip", pair, year))).unwrap());
+            zip.start_file(format!("DAT_ASCII_{}_M1_{}.csv", pair, year), SimpleFileOptions::default()).unwrap();
+            for day in 2..5 {
+                writeln!(zip, "{}01{:02} 120000;1.121200;1.121300;1.121100;1.121270;0", year, day).unwrap();
+            }
+            zip.finish().unwrap();
+        }
+
+        let options = DownloadOptions { cache_dir: Some(cache_dir.path().to_path_buf()), concurrency: Some(1), ..Default::default() };
+        (cache_dir, options)
+    }
+
+    fn range(to_year: i32) -> (DateTime<Utc>, DateTime<Utc>) {
+        (Utc.with_ymd_and_hms(2019, 1, 2, 0, 0, 0).unwrap(), Utc.with_ymd_and_hms(to_year, 12, 31, 0, 0, 0).unwrap())
+    }
+
+    // The staging directories of a pair left by this process
+    fn staging_dirs(pair: &str) -> usize {
+        let prefix = format!(".{}_staging_{}_", pair, process::id());
+        std::fs::read_dir(temp_dir()).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(&prefix))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn the_years_are_streamed_in_order() {
+        let (_cache_dir, options) = cached_options("EURUSD", &[2019, 2020]);
+        let (from_date, to_date) = range(2020);
+
+        let periods = download_stream("EURUSD".into(), from_date, to_date, options)
+            .map(|period| period.map(|(symbol, period, df)| (symbol, period, df.height())))
+            .collect::<Vec<_>>()
+            .await;
+
+        // The last day of 2019 comes with 2020
+        assert_eq!(periods, [Ok(("EURUSD".into(), "2019".into(), 2)), Ok(("EURUSD".into(), "2020".into(), 4))]);
+    }
+
+    #[tokio::test]
+    async fn the_bars_are_streamed_in_utc() {
+        let (_cache_dir, options) = cached_options("EURUSD", &[2019, 2020]);
+        let (from_date, to_date) = range(2020);
+
+        let bars = download_bars("EURUSD".into(), from_date, to_date, options)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<Bar>, String>>()
+            .unwrap();
+
+        assert_eq!(bars.len(), 6);
+        assert_eq!(bars[0].time, Utc.with_ymd_and_hms(2019, 1, 2, 17, 0, 0).unwrap());
+        assert_eq!(bars[5].time, Utc.with_ymd_and_hms(2020, 1, 4, 17, 0, 0).unwrap());
+        assert_eq!((bars[0].open, bars[0].close), (1.1212, 1.12127));
+    }
+
+    #[tokio::test]
+    async fn the_years_are_gathered_in_one_dataframe() {
+        let (_cache_dir, options) = cached_options("EURUSD", &[2019, 2020]);
+        let (from_date, to_date) = range(2020);
+
+        let df = download_dataframe("EURUSD".into(), from_date, to_date, options.clone()).await.unwrap();
+        assert_eq!(df.height(), 6);
+        assert!(df.column("volume").is_ok());
+
+        // The volume is only known to be all zeros once every year is in
+        let options = DownloadOptions { volume: VolumeOptions { policy: VolumePolicy::DropIfZero, tick_dir: None }, ..options };
+        let df = download_dataframe("EURUSD".into(), from_date, to_date, options).await.unwrap();
+        assert!(df.column("volume").is_err());
+    }
+
+    #[tokio::test]
+    async fn dropping_the_stream_stops_the_workers() {
+        // A pair of its own, so the other tests do not stage next to it
+        let (_cache_dir, options) = cached_options("GBPUSD", &[2019, 2020, 2021]);
+        let (from_date, to_date) = range(2021);
+
+        let mut stream = Box::pin(download_stream("GBPUSD".into(), from_date, to_date, options));
+        assert!(stream.next().await.unwrap().is_ok());
+        assert_eq!(staging_dirs("GBPUSD"), 1);
+        drop(stream);
+
+        // The staging directory is only removed once the workers are stopped
+        for _ in 0..100 {
+            if staging_dirs("GBPUSD") == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("The staging directory was not removed");
+    }
+}
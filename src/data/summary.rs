@@ -0,0 +1,76 @@
+use crate::data::{gaps::Gap, validation::ValidationIssue};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::path::PathBuf;
+
+// What happened during a download
+// Returned by `download_data` and shown at the end of the run
+#[derive(Clone, Debug, Default)]
+pub struct DownloadSummary {
+    pub pair: String,
+    // Several files when the output is split
+    pub file_paths: Vec<PathBuf>,
+    pub rows: usize,
+    // Identical bars found more than once, usually at file boundaries
+    pub duplicates: usize,
+    // Timestamps found more than once with different prices
+    // Only the first bar is kept
+    pub conflicts: Vec<NaiveDateTime>,
+    // Bars dropped because the market was closed
+    pub closed_bars: usize,
+    // Bars added to fill the missing minutes
+    pub filled_bars: usize,
+    // Whether every bar has a zero volume, as for every FX pair
+    pub zero_volume: bool,
+    pub volume_dropped: bool,
+    // Bars whose volume is their tick count
+    pub tick_volume_bars: usize,
+    // Gaps reported by HistData inside the requested range
+    pub gaps: Vec<Gap>,
+    pub gaps_file_path: Option<PathBuf>,
+    // Problems found in the bars
+    pub issues: Vec<ValidationIssue>,
+    pub validation_file_path: Option<PathBuf>,
+}
+
+impl DownloadSummary {
+    // Key-value metadata describing the data
+    // Embedded in the Parquet footers and in the SQLite metadata table
+    pub fn metadata(&self, from_date: DateTime<Utc>, to_date: DateTime<Utc>) -> Vec<(String, String)> {
+        let real_gaps = self.gaps.iter().filter(|gap| !gap.expected).collect::<Vec<&Gap>>();
+        let real_gap_minutes = real_gaps.iter().map(|gap| gap.duration.num_minutes()).sum::<i64>();
+
+        vec![
+            ("symbol", self.pair.clone()),
+            ("source", "histdata.com".to_string()),
+            ("timeframe", "M1".to_string()),
+            ("timezone", "UTC".to_string()),
+            ("requested_from", from_date.to_rfc3339()),
+            ("requested_to", to_date.to_rfc3339()),
+            ("tool_version", format!("histdatascraper {}", env!("CARGO_PKG_VERSION"))),
+            ("gap_count", self.gaps.len().to_string()),
+            ("real_gap_count", real_gaps.len().to_string()),
+            ("real_gap_minutes", real_gap_minutes.to_string()),
+            ("volume", self.volume_source().to_string()),
+            // Plain key-values, the writer cannot fill the `sorting_columns` of the row groups
+            ("sorted_by", "datetime".to_string()),
+            ("datetime_order", "ascending,unique".to_string()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+    }
+
+    // Where the volume column comes from
+    fn volume_source(&self) -> &'static str {
+        if self.volume_dropped {
+            "dropped"
+        } else if self.tick_volume_bars > 0 {
+            "tick_count"
+        } else if self.zero_volume {
+            "zero"
+        } else {
+            "histdata"
+        }
+    }
+}
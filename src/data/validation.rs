@@ -0,0 +1,252 @@
+use crate::data::{calendar::MarketCalendar, output::WriteMode, report::{remove_report, save_report}};
+
+use chrono::{DateTime, Duration, NaiveDateTime};
+use polars::prelude::*;
+use std::{
+    collections::VecDeque,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+// Every kind of problem the validation can find
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum IssueKind {
+    HighBelowBody,
+    LowAboveBody,
+    NonPositivePrice,
+    ZeroRangeSpike,
+    OutlierJump,
+    UnexpectedGap,
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            IssueKind::HighBelowBody => "high_below_body",
+            IssueKind::LowAboveBody => "low_above_body",
+            IssueKind::NonPositivePrice => "non_positive_price",
+            IssueKind::ZeroRangeSpike => "zero_range_spike",
+            IssueKind::OutlierJump => "outlier_jump",
+            IssueKind::UnexpectedGap => "unexpected_gap",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationIssue {
+    pub datetime: NaiveDateTime,
+    pub kind: IssueKind,
+    pub detail: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidationOptions {
+    pub enabled: bool,
+    // Fail the run when any issue is found
+    pub strict: bool,
+    // Number of previous bars used to estimate the volatility
+    pub volatility_window: usize,
+    // A move larger than this many standard deviations is an outlier
+    pub outlier_threshold: f64,
+    // A flat bar, with the high equal to the low, moving more than this
+    // many standard deviations is a spike, even below the outlier threshold
+    pub spike_threshold: f64,
+    // Missing data longer than this while the market is open is reported
+    pub max_gap: Duration,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        ValidationOptions {
+            enabled: true,
+            strict: false,
+            volatility_window: 60,
+            outlier_threshold: 10.0,
+            spike_threshold: 3.0,
+            max_gap: Duration::minutes(30),
+        }
+    }
+}
+
+// Check the bars of a sorted DataFrame
+// The issues are returned in time order
+pub fn validate_bars(df: &DataFrame, options: &ValidationOptions, calendar: &MarketCalendar) -> Result<Vec<ValidationIssue>, String> {
+    let read_error = |e: PolarsError| format!("Failed to read bars for validation: {}", e);
+
+    let datetimes = df.column("datetime").and_then(|c| c.datetime()).map_err(read_error)?;
+    let prices = |name: &str| df.column(name).and_then(|c| c.f64()).map_err(read_error);
+    let (opens, highs, lows, closes) = (prices("open")?, prices("high")?, prices("low")?, prices("close")?);
+
+    let mut issues = Vec::new();
+    let mut returns = VecDeque::with_capacity(options.volatility_window);
+    let mut previous: Option<(NaiveDateTime, f64)> = None;
+
+    let bars = datetimes.into_iter()
+        .zip(opens)
+        .zip(highs)
+        .zip(lows)
+        .zip(closes);
+
+    for ((((datetime, open), high), low), close) in bars {
+        let (Some(datetime), Some(open), Some(high), Some(low), Some(close)) = (datetime, open, high, low, close) else {
+            continue;
+        };
+        let Some(datetime) = DateTime::from_timestamp_micros(datetime).map(|dt| dt.naive_utc()) else {
+            continue;
+        };
+
+        let mut issue = |kind, detail: String| issues.push(ValidationIssue { datetime, kind, detail });
+
+        // Consistency of the bar itself
+        if [open, high, low, close].iter().any(|price| *price <= 0.0) {
+            issue(IssueKind::NonPositivePrice, format!("open={} high={} low={} close={}", open, high, low, close));
+        }
+        if high < open.max(close) {
+            issue(IssueKind::HighBelowBody, format!("high={} below max(open={}, close={})", high, open, close));
+        }
+        if low > open.min(close) {
+            issue(IssueKind::LowAboveBody, format!("low={} above min(open={}, close={})", low, open, close));
+        }
+
+        if let Some((previous_datetime, previous_close)) = previous {
+            // Moves compared to the recent volatility
+            let ret = (close / previous_close).ln();
+            // A flat bar is checked on its own, with a lower threshold
+            if let Some(volatility) = volatility(&returns, options.volatility_window)
+                && volatility > 0.0 {
+                let deviations = ret.abs() / volatility;
+                let detail = format!("close moved from {} to {} ({:.1} standard deviations)", previous_close, close, deviations);
+                if high == low && deviations > options.spike_threshold {
+                    issue(IssueKind::ZeroRangeSpike, detail);
+                } else if deviations > options.outlier_threshold {
+                    issue(IssueKind::OutlierJump, detail);
+                }
+            }
+
+            if returns.len() == options.volatility_window {
+                returns.pop_front();
+            }
+            if ret.is_finite() {
+                returns.push_back(ret);
+            }
+
+            // Missing data while the market should be open
+            // Only the long gaps are checked against the calendar
+            if datetime - previous_datetime > options.max_gap {
+                let open_minutes = calendar.open_minutes_between(previous_datetime, datetime);
+                if open_minutes > options.max_gap.num_minutes() {
+                    issue(IssueKind::UnexpectedGap, format!("no bar since {} ({} open minutes)", previous_datetime, open_minutes));
+                }
+            }
+        }
+
+        previous = Some((datetime, close));
+    }
+
+    Ok(issues)
+}
+
+// Standard deviation of the returns once the window is full
+fn volatility(returns: &VecDeque<f64>, window: usize) -> Option<f64> {
+    if window < 2 || returns.len() < window {
+        return None;
+    }
+
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let variance = returns.iter().map(|ret| (ret - mean).powi(2)).sum::<f64>() / (n - 1.0);
+
+    Some(variance.sqrt())
+}
+
+pub fn issues_to_dataframe(issues: &[ValidationIssue]) -> Result<DataFrame, String> {
+    let datetime = Column::new("datetime".into(), issues.iter().map(|issue| issue.datetime.and_utc().timestamp_micros()).collect::<Vec<i64>>());
+    let kind = Column::new("kind".into(), issues.iter().map(|issue| issue.kind.to_string()).collect::<Vec<String>>());
+    let detail = Column::new("detail".into(), issues.iter().map(|issue| issue.detail.clone()).collect::<Vec<String>>());
+
+    datetime.cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+        .and_then(|datetime| DataFrame::new(vec![datetime, kind, detail]))
+        .map_err(|e| format!("Failed to build validation DataFrame: {}", e))
+}
+
+// Write the issues next to the data as `<PAIR>_validation.csv`
+// Nothing is written when no issue was found, and the file of a previous run is removed
+// The existing file follows the write mode of the data
+pub fn save_validation_report(issues: &[ValidationIssue], data_dir: &Path, pair: &str, write_mode: WriteMode) -> Result<Option<PathBuf>, String> {
+    if issues.is_empty() {
+        remove_report(data_dir, pair, "validation", write_mode)?;
+        return Ok(None);
+    }
+
+    save_report(&mut issues_to_dataframe(issues)?, data_dir, pair, "validation", write_mode).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bars one minute apart, each given as (open, high, low, close)
+    fn bars(prices: &[(f64, f64, f64, f64)]) -> DataFrame {
+        let start = NaiveDateTime::parse_from_str("2020-01-06 10:00", "%Y-%m-%d %H:%M").unwrap().and_utc().timestamp_micros();
+        let micros = (0..prices.len() as i64).map(|i| start + i * 60_000_000).collect::<Vec<i64>>();
+        let price = |name: &str, f: fn(&(f64, f64, f64, f64)) -> f64| Column::new(name.into(), prices.iter().map(f).collect::<Vec<f64>>());
+
+        DataFrame::new(vec![
+            Column::new("datetime".into(), micros).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap(),
+            price("open", |bar| bar.0),
+            price("high", |bar| bar.1),
+            price("low", |bar| bar.2),
+            price("close", |bar| bar.3),
+        ])
+        .unwrap()
+    }
+
+    // Closes going up and down by one point around 1.1
+    fn quiet_bars(count: usize) -> Vec<(f64, f64, f64, f64)> {
+        (0..count).map(|i| {
+            let close = if i % 2 == 0 { 1.1000 } else { 1.1001 };
+            (close, close + 0.0001, close - 0.0001, close)
+        })
+        .collect()
+    }
+
+    fn kinds(prices: &[(f64, f64, f64, f64)]) -> Vec<IssueKind> {
+        let options = ValidationOptions { volatility_window: 10, ..Default::default() };
+
+        validate_bars(&bars(prices), &options, &MarketCalendar::default())
+            .unwrap()
+            .into_iter()
+            .map(|issue| issue.kind)
+            .collect()
+    }
+
+    #[test]
+    fn inconsistent_bars_are_reported() {
+        assert_eq!(kinds(&[(1.1, 1.09, 1.08, 1.1)]), vec![IssueKind::HighBelowBody]);
+        assert_eq!(kinds(&[(1.1, 1.12, 1.105, 1.11)]), vec![IssueKind::LowAboveBody]);
+        assert_eq!(kinds(&[(0.0, 1.1, 0.0, 1.1)]), vec![IssueKind::NonPositivePrice]);
+        assert!(kinds(&quiet_bars(30)).is_empty());
+    }
+
+    #[test]
+    fn a_flat_bar_after_a_large_move_is_a_spike() {
+        // About 4 standard deviations, below the outlier threshold
+        let mut prices = quiet_bars(20);
+        prices.push((1.1005, 1.1005, 1.1005, 1.1005));
+        assert_eq!(kinds(&prices), vec![IssueKind::ZeroRangeSpike]);
+
+        // The same move with a range is not an issue
+        let mut prices = quiet_bars(20);
+        prices.push((1.1001, 1.1006, 1.1000, 1.1005));
+        assert!(kinds(&prices).is_empty());
+    }
+
+    #[test]
+    fn a_large_jump_is_an_outlier() {
+        let mut prices = quiet_bars(20);
+        prices.push((1.1001, 1.1100, 1.1000, 1.1100));
+        assert_eq!(kinds(&prices), vec![IssueKind::OutlierJump]);
+    }
+}
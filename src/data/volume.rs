@@ -0,0 +1,254 @@
+use crate::data::{
+    archive::{ArchiveLimits, expected_tick_entry_name, read_archive},
+    bars::read_ticks,
+    handler::HISTDATA_UTC_OFFSET_HOURS,
+};
+
+use chrono::{DateTime, Datelike, Duration};
+use polars::prelude::*;
+use std::{
+    collections::BTreeSet,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+// What to do with the volume column
+// HistData only has a volume for a few instruments, it is zero for every FX pair
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VolumePolicy {
+    #[default]
+    Keep,
+    // Drop the column when every bar has a zero volume
+    DropIfZero,
+    // Count the ticks of each bar where tick data is available
+    // The other bars keep their volume
+    TickCount,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VolumeOptions {
+    pub policy: VolumePolicy,
+    // Directory holding the HistData tick archives
+    // `HISTDATA_COM_ASCII_<PAIR>_T<YYYYMM>.zip`, the data directory when `None`
+    pub tick_dir: Option<PathBuf>,
+}
+
+impl VolumeOptions {
+    // Directory the tick archives are read from
+    // The streams write no data directory, so they need `tick_dir` to count ticks
+    pub fn tick_archive_dir(&self, data_dir: Option<&Path>) -> Result<PathBuf, String> {
+        match (&self.tick_dir, data_dir) {
+            (Some(tick_dir), _) => Ok(tick_dir.clone()),
+            (None, Some(data_dir)) => Ok(data_dir.to_path_buf()),
+            (None, None) if self.policy == VolumePolicy::TickCount => Err("No directory to read the tick archives from, set the volume tick_dir".into()),
+            (None, None) => Ok(PathBuf::new()),
+        }
+    }
+}
+
+// File name of a monthly HistData tick archive
+pub fn tick_archive_name(pair: &str, year: i32, month: u32) -> String {
+    format!("HISTDATA_COM_ASCII_{}_T{}{:02}.zip", pair.to_uppercase(), year, month)
+}
+
+// Count the ticks of each UTC minute of a HistData ASCII tick file
+// The lines are parsed by `read_ticks`, which converts their times to UTC
+pub fn count_ticks<R: BufRead>(reader: R) -> Result<DataFrame, String> {
+    let mut minutes: Vec<i64> = Vec::new();
+    let mut counts: Vec<i64> = Vec::new();
+
+    // The ticks are sorted, so each minute is a run of lines
+    for tick in read_ticks(reader) {
+        let minute = tick?.time.timestamp_micros().div_euclid(60_000_000) * 60_000_000;
+        if minutes.last() == Some(&minute) {
+            *counts.last_mut().unwrap() += 1;
+            continue;
+        }
+
+        minutes.push(minute);
+        counts.push(1);
+    }
+
+    let datetime = Column::new("datetime".into(), minutes)
+        .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+        .map_err(|e| format!("Failed to build datetime column: {}", e))?;
+
+    // A minute can show up twice if the file is not sorted
+    DataFrame::new(vec![datetime, Column::new("tick_count".into(), counts)])
+        .and_then(|df| {
+            df.lazy()
+                .group_by([col("datetime")])
+                .agg([col("tick_count").sum()])
+                .sort(["datetime"], Default::default())
+                .collect()
+        })
+        .map_err(|e| format!("Failed to count ticks: {}", e))
+}
+
+// Tick counts per UTC minute from the archive of a month
+// `None` when the archive is not in the directory
+pub fn read_tick_counts(tick_dir: &Path, pair: &str, year: i32, month: u32, limits: &ArchiveLimits) -> Result<Option<DataFrame>, String> {
+    let path = tick_dir.join(tick_archive_name(pair, year, month));
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let archive = read_archive(&path, &expected_tick_entry_name(pair, year, month), limits)?;
+    count_ticks(archive.data.as_slice())
+        .map(Some)
+        .map_err(|e| format!("{} in {}", e, path.display()))
+}
+
+// Replace the volume of the bars with their tick count
+// where the tick archive of the month is in the directory
+// Returns the bars and how many of them got a tick count
+pub fn add_tick_counts(df: &DataFrame, tick_dir: &Path, pair: &str, limits: &ArchiveLimits) -> Result<(DataFrame, usize), String> {
+    // The tick archives are split on the source timezone months
+    let source_offset = Duration::hours(HISTDATA_UTC_OFFSET_HOURS).num_microseconds().unwrap();
+    let months = df.column("datetime")
+        .and_then(|c| c.datetime())
+        .map_err(|e| format!("Failed to read datetimes: {}", e))?
+        .into_no_null_iter()
+        .filter_map(|micros| DateTime::from_timestamp_micros(micros + source_offset))
+        .map(|datetime| (datetime.year(), datetime.month()))
+        .collect::<BTreeSet<(i32, u32)>>();
+
+    let mut counts = Vec::new();
+    for (year, month) in months {
+        counts.extend(read_tick_counts(tick_dir, pair, year, month, limits)?);
+    }
+    if counts.is_empty() {
+        return Ok((df.clone(), 0));
+    }
+
+    let counts = concat(counts.into_iter().map(|counts| counts.lazy()).collect::<Vec<LazyFrame>>(), UnionArgs::default())
+        .map_err(|e| format!("Failed to merge tick counts: {}", e))?;
+    let df = df.clone().lazy()
+        .left_join(counts, col("datetime"), col("datetime"))
+        .with_column(
+            when(col("tick_count").is_not_null())
+                .then(col("tick_count"))
+                .otherwise(col("volume"))
+                .cast(DataType::Int64)
+                .alias("volume"),
+        )
+        .collect()
+        .map_err(|e| format!("Failed to add tick counts: {}", e))?;
+
+    let counted = df.height() - df.column("tick_count").map(|c| c.null_count()).unwrap_or(df.height());
+    let df = df.drop("tick_count").map_err(|e| format!("Failed to add tick counts: {}", e))?;
+
+    Ok((df, counted))
+}
+
+// Whether every bar has a zero or missing volume
+pub fn is_zero_volume(df: &DataFrame) -> Result<bool, String> {
+    let Ok(volume) = df.column("volume") else {
+        return Ok(true);
+    };
+
+    Ok(volume.cast(&DataType::Int64)
+        .and_then(|c| c.i64().cloned())
+        .map_err(|e| format!("Failed to read volumes: {}", e))?
+        .into_iter()
+        .all(|volume| volume.unwrap_or_default() == 0))
+}
+
+// The bars with a zero volume column if it was dropped
+// For the layouts that always have a volume
+pub fn with_volume(df: &DataFrame) -> Result<DataFrame, String> {
+    if df.column("volume").is_ok() {
+        return Ok(df.clone());
+    }
+
+    df.clone().lazy()
+        .with_column(lit(0i64).alias("volume"))
+        .collect()
+        .map_err(|e| format!("Failed to add volume column: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, io::Write};
+    use tempfile::tempdir;
+    use ::zip::{ZipWriter, write::SimpleFileOptions};
+
+    // EST ticks, the last one is in the January archive but in February in UTC
+    const TICKS: &str = "20200114 215959900,1.109000,1.109100,0
+20200114 220000100,1.109010,1.109110,0
+20200114 220030000,1.109020,1.109120,0
+20200114 220059999,1.109030,1.109130,0
+20200131 230000000,1.109040,1.109140,0
+";
+
+    fn utc(datetime: &str) -> i64 {
+        chrono::NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap().and_utc().timestamp_micros()
+    }
+
+    fn column(df: &DataFrame, name: &str) -> Vec<i64> {
+        df.column(name).unwrap().cast(&DataType::Int64).unwrap().i64().unwrap().into_no_null_iter().collect()
+    }
+
+    fn tick_archive(tick_dir: &Path, year: i32, month: u32, ticks: &str) {
+        let mut zip = ZipWriter::new(File::create(tick_dir.join(tick_archive_name("EURUSD", year, month))).unwrap());
+        zip.start_file(expected_tick_entry_name("EURUSD", year, month), SimpleFileOptions::default()).unwrap();
+        zip.write_all(ticks.as_bytes()).unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn the_tick_archives_are_read_from_the_data_directory_by_default() {
+        let options = VolumeOptions { policy: VolumePolicy::TickCount, tick_dir: None };
+        assert_eq!(options.tick_archive_dir(Some(Path::new("data"))).unwrap(), Path::new("data"));
+        assert!(options.tick_archive_dir(None).is_err());
+
+        let options = VolumeOptions { tick_dir: Some("ticks".into()), ..options };
+        assert_eq!(options.tick_archive_dir(Some(Path::new("data"))).unwrap(), Path::new("ticks"));
+        assert_eq!(options.tick_archive_dir(None).unwrap(), Path::new("ticks"));
+    }
+
+    #[test]
+    fn the_ticks_are_counted_per_utc_minute() {
+        let counts = count_ticks(TICKS.as_bytes()).unwrap();
+
+        assert_eq!(
+            column(&counts, "datetime"),
+            [utc("2020-01-15 02:59"), utc("2020-01-15 03:00"), utc("2020-02-01 04:00")],
+        );
+        assert_eq!(column(&counts, "tick_count"), [1, 3, 1]);
+
+        let error = count_ticks(format!("{}20200131 2300,1.1\n", TICKS).as_bytes()).unwrap_err();
+        assert!(error.starts_with("Invalid tick on line 6"), "{}", error);
+    }
+
+    #[test]
+    fn the_counts_are_read_from_the_archive_of_the_month() {
+        let dir = tempdir().unwrap();
+        tick_archive(dir.path(), 2020, 1, TICKS);
+
+        let counts = read_tick_counts(dir.path(), "EURUSD", 2020, 1, &ArchiveLimits::default()).unwrap().unwrap();
+        assert_eq!(counts.height(), 3);
+        assert!(read_tick_counts(dir.path(), "EURUSD", 2020, 2, &ArchiveLimits::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn the_volume_is_replaced_where_the_ticks_are_known() {
+        let dir = tempdir().unwrap();
+        tick_archive(dir.path(), 2020, 1, TICKS);
+
+        // The last bar is in February in EST too, and its archive is missing
+        let datetimes = ["2020-01-15 02:59", "2020-01-15 03:00", "2020-01-15 03:01", "2020-02-01 04:00", "2020-02-03 10:00"];
+        let bars = DataFrame::new(vec![
+            Column::new("datetime".into(), datetimes.map(utc).to_vec()).cast(&DataType::Datetime(TimeUnit::Microseconds, None)).unwrap(),
+            Column::new("close".into(), vec![1.1091; 5]),
+            Column::new("volume".into(), vec![7i64; 5]),
+        ])
+        .unwrap();
+
+        let (bars, counted) = add_tick_counts(&bars, dir.path(), "EURUSD", &ArchiveLimits::default()).unwrap();
+        assert_eq!(counted, 3);
+        assert_eq!(column(&bars, "volume"), [1, 3, 7, 1, 7]);
+        assert_eq!(bars.get_column_names(), ["datetime", "close", "volume"]);
+    }
+}
@@ -1,6 +1,8 @@
 use histdatascraper::data::handler::download_data;
+use histdatascraper::data::options::DownloadOptions;
+use histdatascraper::data::output::OutputOptions;
 use histdatascraper::data::pairs::build_pairs;
-use histdatascraper::utils::terminal::{choose_datatype, choose_dates, choose_destination, choose_pair, show_progress};
+use histdatascraper::utils::terminal::{choose_datatype, choose_dates, choose_destination, choose_pair, choose_sessions, choose_write_mode, show_progress, show_summary};
 
 use tokio::{task::spawn, sync::mpsc::channel};
 
@@ -13,19 +15,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // Let the user choose a pair
     // the dates
-    // and the destination
+    // the destination
+    // the trading sessions
+    // the format and what to do with an existing file
     let pair = choose_pair().await;
     let (from_date, to_date) = choose_dates(pair.clone()).await;
     let data_dir = choose_destination();
-    let data_type = choose_datatype();
+    let sessions = choose_sessions();
+    let format = choose_datatype();
+    let write_mode = choose_write_mode(&data_dir, &pair, format);
+    let options = DownloadOptions {
+        sessions,
+        output: OutputOptions {
+            write_mode,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
 
     // Create the main task with the sender
     // and the receiver
-    let _ = spawn(download_data(pair.clone(), from_date, to_date, data_dir, data_type, tx));
+    let download_task = spawn(download_data(pair.clone(), from_date, to_date, data_dir, format, options, tx));
 
     // Create a task to show the progress
     let rx_task = spawn(show_progress(rx));
     rx_task.await.unwrap();
 
+    // Show what happened
+    // or the error if the download failed
+    match download_task.await? {
+        Ok(summary) => show_summary(&summary),
+        Err(e) => eprintln!("Failed to download data: {}", e),
+    }
+
     Ok(())
 }
\ No newline at end of file
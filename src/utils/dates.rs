@@ -0,0 +1,276 @@
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+
+// Which side of a range a date expression is parsed for
+// A day, month, quarter or year expands to its first second for the start
+// and to its last second for the end
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Start,
+    End,
+}
+
+// Ranges that can be picked without typing any date
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DatePreset {
+    FullHistory,
+    LastTwelveMonths,
+    YearToDate,
+    Year(i32),
+}
+
+impl DatePreset {
+    // Resolve the preset against the range available for a pair
+    // Relative presets are anchored on the last available date, not on today,
+    // because HistData only publishes full years
+    pub fn resolve(&self, min_date: DateTime<Utc>, max_date: DateTime<Utc>) -> Result<(DateTime<Utc>, DateTime<Utc>), String> {
+        let (from_date, to_date) = match self {
+            DatePreset::FullHistory => (min_date, max_date),
+            DatePreset::LastTwelveMonths => (parse_date("-12m", Bound::Start, max_date)?, max_date),
+            DatePreset::YearToDate => (parse_date(&max_date.year().to_string(), Bound::Start, max_date)?, max_date),
+            DatePreset::Year(year) => (
+                parse_date(&year.to_string(), Bound::Start, max_date)?,
+                parse_date(&year.to_string(), Bound::End, max_date)?,
+            ),
+        };
+
+        // Presets may reach before the first available date
+        // In that case we simply start at the first available date
+        let from_date = from_date.max(min_date);
+        check_range(from_date, to_date, min_date, max_date)?;
+
+        Ok((from_date, to_date))
+    }
+}
+
+// Last date for which HistData publishes 1-minute bars
+pub fn max_available_date() -> DateTime<Utc> {
+    let date = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap().and_time(end_of_day());
+
+    Utc.from_utc_datetime(&date)
+}
+
+// Parse a single date expression
+// Accepted forms are:
+// - `YYYY`, `YYYY-MM`, `YYYYQn` and `YYYY-MM-DD`, expanded depending on the bound
+// - `YYYY-MM-DD HH:MM` and `YYYY-MM-DD HH:MM:SS` (a `T` separator is also accepted)
+// - relative expressions such as `-3y`, `-6m`, `-2w` or `-10d`, counted back from `anchor`
+pub fn parse_date(input: &str, bound: Bound, anchor: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    let input = input.trim();
+
+    if input.is_empty() {
+        return Err("Please enter a date".to_string());
+    }
+
+    let date = if let Some(relative) = input.strip_prefix('-') {
+        parse_relative(relative, bound, anchor.naive_utc())?
+    } else if let Some((year, quarter)) = input.split_once(['Q', 'q']) {
+        parse_quarter(year, quarter, bound)?
+    } else if input.len() == 4 {
+        let year = parse_year(input)?;
+        expand_period(
+            NaiveDate::from_ymd_opt(year, 1, 1).unwrap(),
+            NaiveDate::from_ymd_opt(year, 12, 31).unwrap(),
+            bound,
+        )
+    } else if input.len() == 7 {
+        let first_day = NaiveDate::parse_from_str(&format!("{}-01", input), "%Y-%m-%d")
+            .map_err(|_| format!("Invalid month '{}', expected YYYY-MM", input))?;
+        expand_period(first_day, last_day_of_month(first_day), bound)
+    } else if input.len() == 10 {
+        let day = NaiveDate::parse_from_str(input, "%Y-%m-%d")
+            .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD", input))?;
+        expand_period(day, day, bound)
+    } else {
+        parse_date_time(input)?
+    };
+
+    Ok(Utc.from_utc_datetime(&date))
+}
+
+// Check that a date is inside the available range
+// The error message explains why the date was refused
+pub fn check_bounds(date: DateTime<Utc>, min_date: DateTime<Utc>, max_date: DateTime<Utc>) -> Result<(), String> {
+    if date < min_date {
+        return Err(format!("{} is before the first available date {}", format_date(date), format_date(min_date)));
+    }
+    if date > max_date {
+        return Err(format!("{} is after the last available date {}", format_date(date), format_date(max_date)));
+    }
+
+    Ok(())
+}
+
+// Check both ends of a range and their order
+pub fn check_range(from_date: DateTime<Utc>, to_date: DateTime<Utc>, min_date: DateTime<Utc>, max_date: DateTime<Utc>) -> Result<(), String> {
+    check_bounds(from_date, min_date, max_date)?;
+    check_bounds(to_date, min_date, max_date)?;
+
+    if from_date > to_date {
+        return Err(format!("The start {} is after the end {}", format_date(from_date), format_date(to_date)));
+    }
+
+    Ok(())
+}
+
+// Only show the time when it carries information
+pub fn format_date(date: DateTime<Utc>) -> String {
+    if date.time() == NaiveTime::MIN || date.time() == end_of_day() {
+        date.format("%Y-%m-%d").to_string()
+    } else {
+        date.format("%Y-%m-%d %H:%M:%S").to_string()
+    }
+}
+
+fn end_of_day() -> NaiveTime {
+    NaiveTime::from_hms_opt(23, 59, 59).unwrap()
+}
+
+fn expand_period(first_day: NaiveDate, last_day: NaiveDate, bound: Bound) -> NaiveDateTime {
+    match bound {
+        Bound::Start => first_day.and_time(NaiveTime::MIN),
+        Bound::End => last_day.and_time(end_of_day()),
+    }
+}
+
+fn last_day_of_month(first_day: NaiveDate) -> NaiveDate {
+    first_day.checked_add_months(Months::new(1)).unwrap().pred_opt().unwrap()
+}
+
+fn parse_year(input: &str) -> Result<i32, String> {
+    match input.parse::<i32>() {
+        Ok(year) if input.len() == 4 => Ok(year),
+        _ => Err(format!("Invalid year '{}', expected YYYY", input)),
+    }
+}
+
+fn parse_quarter(year: &str, quarter: &str, bound: Bound) -> Result<NaiveDateTime, String> {
+    let year = parse_year(year)?;
+    let quarter = match quarter.parse::<u32>() {
+        Ok(q) if (1..=4).contains(&q) => q,
+        _ => return Err(format!("Invalid quarter 'Q{}', expected Q1 to Q4", quarter)),
+    };
+
+    let first_day = NaiveDate::from_ymd_opt(year, (quarter - 1) * 3 + 1, 1).unwrap();
+    let last_day = last_day_of_month(first_day.checked_add_months(Months::new(2)).unwrap());
+
+    Ok(expand_period(first_day, last_day, bound))
+}
+
+fn parse_date_time(input: &str) -> Result<NaiveDateTime, String> {
+    let input = input.replacen('T', " ", 1);
+
+    NaiveDateTime::parse_from_str(&input, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(&input, "%Y-%m-%d %H:%M"))
+        .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD or YYYY-MM-DD HH:MM[:SS]", input))
+}
+
+// Relative expressions are whole days counted back from the anchor's day
+// So `-3y` as a start covers exactly three years up to the anchor
+fn parse_relative(input: &str, bound: Bound, anchor: NaiveDateTime) -> Result<NaiveDateTime, String> {
+    let error = || format!("Invalid relative date '-{}', expected a number followed by d, w, m or y (e.g. -3y)", input);
+
+    if input.len() < 2 {
+        return Err(error());
+    }
+
+    let (amount, unit) = input.split_at(input.len() - 1);
+    let amount = amount.parse::<u32>().map_err(|_| error())?;
+
+    let anchor_day = anchor.date();
+    let day = match unit {
+        "d" | "D" => anchor_day.checked_sub_days(Days::new(amount as u64)),
+        "w" | "W" => anchor_day.checked_sub_days(Days::new(amount as u64 * 7)),
+        "m" | "M" => anchor_day.checked_sub_months(Months::new(amount)),
+        "y" | "Y" => anchor_day.checked_sub_months(Months::new(amount * 12)),
+        _ => return Err(error()),
+    }
+    .ok_or_else(error)?;
+
+    Ok(match bound {
+        Bound::Start => day.succ_opt().unwrap().and_time(NaiveTime::MIN),
+        Bound::End => day.and_time(end_of_day()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, second).unwrap()
+    }
+
+    #[test]
+    fn periods_expand_to_their_bounds() {
+        let anchor = max_available_date();
+
+        assert_eq!(parse_date("2019", Bound::Start, anchor).unwrap(), utc(2019, 1, 1, 0, 0, 0));
+        assert_eq!(parse_date("2019", Bound::End, anchor).unwrap(), utc(2019, 12, 31, 23, 59, 59));
+        assert_eq!(parse_date("2020-02", Bound::End, anchor).unwrap(), utc(2020, 2, 29, 23, 59, 59));
+        assert_eq!(parse_date("2021-03-15", Bound::Start, anchor).unwrap(), utc(2021, 3, 15, 0, 0, 0));
+        assert_eq!(parse_date("2021-03-15", Bound::End, anchor).unwrap(), utc(2021, 3, 15, 23, 59, 59));
+    }
+
+    #[test]
+    fn quarters_cover_three_months() {
+        let anchor = max_available_date();
+
+        assert_eq!(parse_date("2020Q2", Bound::Start, anchor).unwrap(), utc(2020, 4, 1, 0, 0, 0));
+        assert_eq!(parse_date("2020q2", Bound::End, anchor).unwrap(), utc(2020, 6, 30, 23, 59, 59));
+        assert_eq!(parse_date("2020Q4", Bound::End, anchor).unwrap(), utc(2020, 12, 31, 23, 59, 59));
+        assert!(parse_date("2020Q5", Bound::Start, anchor).is_err());
+    }
+
+    #[test]
+    fn intraday_bounds_are_kept() {
+        let anchor = max_available_date();
+
+        assert_eq!(parse_date("2021-03-15 14:30", Bound::Start, anchor).unwrap(), utc(2021, 3, 15, 14, 30, 0));
+        assert_eq!(parse_date("2021-03-15T14:30:15", Bound::End, anchor).unwrap(), utc(2021, 3, 15, 14, 30, 15));
+    }
+
+    #[test]
+    fn relative_dates_count_back_from_the_anchor() {
+        let anchor = utc(2024, 12, 31, 23, 59, 59);
+
+        assert_eq!(parse_date("-3y", Bound::Start, anchor).unwrap(), utc(2022, 1, 1, 0, 0, 0));
+        assert_eq!(parse_date("-6m", Bound::Start, anchor).unwrap(), utc(2024, 7, 1, 0, 0, 0));
+        assert_eq!(parse_date("-2w", Bound::Start, anchor).unwrap(), utc(2024, 12, 18, 0, 0, 0));
+        assert_eq!(parse_date("-10d", Bound::End, anchor).unwrap(), utc(2024, 12, 21, 23, 59, 59));
+        assert!(parse_date("-3x", Bound::Start, anchor).is_err());
+        assert!(parse_date("-y", Bound::Start, anchor).is_err());
+    }
+
+    #[test]
+    fn invalid_dates_are_refused() {
+        let anchor = max_available_date();
+
+        for input in ["", "20x9", "2020-13", "2020-02-30", "2020-02-03 25:00"] {
+            assert!(parse_date(input, Bound::Start, anchor).is_err(), "{} was accepted", input);
+        }
+    }
+
+    #[test]
+    fn presets_are_clamped_to_the_available_range() {
+        let (min_date, max_date) = (utc(2010, 1, 1, 0, 0, 0), max_available_date());
+
+        assert_eq!(DatePreset::FullHistory.resolve(min_date, max_date).unwrap(), (min_date, max_date));
+        assert_eq!(DatePreset::YearToDate.resolve(min_date, max_date).unwrap(), (utc(2024, 1, 1, 0, 0, 0), max_date));
+        assert_eq!(DatePreset::LastTwelveMonths.resolve(min_date, max_date).unwrap(), (utc(2024, 1, 1, 0, 0, 0), max_date));
+        assert_eq!(DatePreset::Year(2015).resolve(min_date, max_date).unwrap(), (utc(2015, 1, 1, 0, 0, 0), utc(2015, 12, 31, 23, 59, 59)));
+
+        let late_min_date = utc(2024, 6, 1, 0, 0, 0);
+        assert_eq!(DatePreset::LastTwelveMonths.resolve(late_min_date, max_date).unwrap().0, late_min_date);
+        assert!(DatePreset::Year(2005).resolve(min_date, max_date).is_err());
+    }
+
+    #[test]
+    fn ranges_are_checked() {
+        let (min_date, max_date) = (utc(2010, 1, 1, 0, 0, 0), max_available_date());
+
+        assert!(check_range(utc(2012, 1, 1, 0, 0, 0), utc(2013, 1, 1, 0, 0, 0), min_date, max_date).is_ok());
+        assert!(check_range(utc(2013, 1, 1, 0, 0, 0), utc(2012, 1, 1, 0, 0, 0), min_date, max_date).is_err());
+        assert!(check_range(utc(2009, 1, 1, 0, 0, 0), utc(2012, 1, 1, 0, 0, 0), min_date, max_date).is_err());
+        assert!(check_range(utc(2012, 1, 1, 0, 0, 0), utc(2025, 1, 1, 0, 0, 0), min_date, max_date).is_err());
+    }
+}
@@ -33,7 +33,7 @@ pub async fn launch_driver(port: usize) -> Result<(), String> {
 pub async fn close_driver(port: usize) -> Result<(), String> {
     // Get the process ID of the browser
     let output = Command::new("lsof")
-        .args(&["-ti", &format!(":{}", port)]) 
+        .args(["-ti", &format!(":{}", port)]) 
         .output().unwrap();
 
     // If we find out
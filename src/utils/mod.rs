@@ -1,3 +1,5 @@
+pub mod dates;
 pub mod driver;
 pub mod terminal;
-pub mod utils;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod utils;
@@ -1,12 +1,21 @@
 use crate::PAIRS;
+use crate::data::{
+        options::SessionOptions,
+        output::{OutputFormat, WriteMode, default_output_path, report_write_mode},
+        report::report_path,
+        sessions::TradingSession,
+        summary::DownloadSummary,
+};
+use crate::utils::dates::{Bound, DatePreset, check_bounds, check_range, format_date, max_available_date, parse_date};
 
-use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono::{DateTime, Datelike, Utc};
 use dialoguer::Select;
 use indicatif::{ProgressBar, ProgressStyle};
-use inquire::Text;
+use inquire::{Text, validator::Validation};
 use std::{
+        collections::BTreeMap,
         fs::{canonicalize, create_dir_all},
-        path::PathBuf,
+        path::{Path, PathBuf},
         process::exit,
 };
 use tokio::sync::mpsc::Receiver;
@@ -38,6 +47,83 @@ pub async fn show_progress(mut rx: Receiver<usize>)  {
     }
 }
 
+// Function to display what happened during the download
+pub fn show_summary(summary: &DownloadSummary) {
+    match summary.file_paths.as_slice() {
+        [file_path] => println!("Saved {} bars of {} to {}", summary.rows, summary.pair, file_path.display()),
+        file_paths => {
+            println!("Saved {} bars of {} to {} files:", summary.rows, summary.pair, file_paths.len());
+            for file_path in file_paths {
+                println!("  {}", file_path.display());
+            }
+        }
+    }
+
+    if summary.closed_bars > 0 {
+        println!("Dropped {} bars stamped while the market was closed", summary.closed_bars);
+    }
+
+    if summary.filled_bars > 0 {
+        println!("Filled {} missing minutes", summary.filled_bars);
+    }
+
+    if summary.volume_dropped {
+        println!("Dropped the volume column, zero for every bar");
+    }
+
+    if summary.tick_volume_bars > 0 {
+        println!("Used the tick count as volume for {} bars", summary.tick_volume_bars);
+    }
+
+    if summary.duplicates > 0 {
+        println!("Removed {} duplicated bars", summary.duplicates);
+    }
+
+    if !summary.conflicts.is_empty() {
+        println!("Found {} timestamps with conflicting bars, the first bar was kept:", summary.conflicts.len());
+        for datetime in summary.conflicts.iter().take(10) {
+            println!("  {}", datetime);
+        }
+        if summary.conflicts.len() > 10 {
+            println!("  ... and {} more", summary.conflicts.len() - 10);
+        }
+    }
+
+    if !summary.gaps.is_empty() {
+        let expected = summary.gaps.iter().filter(|gap| gap.expected).count();
+        println!("HistData reported {} gaps, {} of them while the market was closed", summary.gaps.len(), expected);
+
+        // Only the real gaps are worth detailing
+        let real_gaps = summary.gaps.iter().filter(|gap| !gap.expected).collect::<Vec<_>>();
+        if let Some(longest) = real_gaps.iter().max_by_key(|gap| gap.duration) {
+            let total = real_gaps.iter().map(|gap| gap.duration.num_seconds()).sum::<i64>();
+            println!(
+                "The {} real gaps add up to {} minutes, the longest from {} to {}",
+                real_gaps.len(), total / 60, longest.start, longest.end,
+            );
+        }
+    }
+    if let Some(gaps_file_path) = &summary.gaps_file_path {
+        println!("Gaps saved to {}", gaps_file_path.display());
+    }
+
+    if !summary.issues.is_empty() {
+        // Count the issues of each kind
+        let mut counts = BTreeMap::new();
+        for issue in &summary.issues {
+            *counts.entry(issue.kind).or_insert(0) += 1;
+        }
+
+        println!("Validation found {} issues:", summary.issues.len());
+        for (kind, count) in counts {
+            println!("  {}: {}", kind, count);
+        }
+    }
+    if let Some(validation_file_path) = &summary.validation_file_path {
+        println!("Validation report saved to {}", validation_file_path.display());
+    }
+}
+
 pub async fn choose_pair() -> String {
     clear_terminal();
 
@@ -73,53 +159,81 @@ pub async fn choose_dates(pair: String) -> (DateTime<Utc>, DateTime<Utc>) {
     // Get the minimum date from the PAIRS static variable
     let min_date = {
         let pairs = PAIRS.lock().await;
-        let pair = pairs.get(&pair).unwrap();
 
-        pair.naive_utc()
+        *pairs.get(&pair).unwrap()
     };
-    let max_date = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap().and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap());
-
-    // Initialize the beginning and end dates
-    let beginning_date;
-    let end_date;
-
-    // While the user doesn't enter a valid date
-    // Ask for the end date
-    loop {
-        let date_input = Text::new(&format!("Enter beginning date (YYYY-MM-DD), between {} and {}: ", &min_date.format("%Y-%m-%d").to_string(), &max_date.format("%Y-%m-%d").to_string()))
-            .prompt().unwrap();
-
-        match NaiveDateTime::parse_from_str(&format!("{} 00:00:00", date_input), "%Y-%m-%d %H:%M:%S") {
-            Ok(date) if date >= min_date && date <= max_date => {
-                beginning_date = date;
-                break;
-            }
-            Ok(_) => {}
-            Err(_) => {}
-        }
-    }
+    let max_date = max_available_date();
 
-    // While the user doesn't enter a valid date
-    // Ask for the end date
-    loop {
-        let date_input = Text::new(&format!("Enter end date (YYYY-MM-DD), between {} and {}: ", &beginning_date.format("%Y-%m-%d").to_string(), &max_date.format("%Y-%m-%d").to_string()))
-            .prompt().unwrap();
+    // Let the user pick a preset
+    // or type the range by hand
+    let presets = vec!["Custom range", "Full history", "Last 12 months", "Year to date", "Specific year"];
+    let selection = Select::new()
+        .with_prompt("Select a date range")
+        .default(0)
+        .items(&presets)
+        .interact()
+        .unwrap();
 
-        match NaiveDateTime::parse_from_str(&format!("{} 23:59:59", date_input), "%Y-%m-%d %H:%M:%S") {
-            Ok(date) if date >= beginning_date && date <= max_date => {
-                end_date = date;
-                break;
-            }
-            Ok(_) => {}
-            Err(_) => {}
-        }
-    }
+    let preset = match selection {
+        1 => DatePreset::FullHistory,
+        2 => DatePreset::LastTwelveMonths,
+        3 => DatePreset::YearToDate,
+        4 => DatePreset::Year(choose_year(min_date, max_date)),
+        _ => return choose_custom_dates(min_date, max_date),
+    };
 
-    // Convert the dates to UTC
-    return (
-        Utc.from_utc_datetime(&beginning_date),
-        Utc.from_utc_datetime(&end_date),
-    );
+    // The presets are always valid
+    // because they are clamped to the available range
+    preset.resolve(min_date, max_date).unwrap()
+}
+
+fn choose_year(min_date: DateTime<Utc>, max_date: DateTime<Utc>) -> i32 {
+    let (min_year, max_year) = (min_date.year(), max_date.year());
+
+    let year_input = Text::new(&format!("Enter the year, between {} and {}: ", min_year, max_year))
+        .with_validator(move |input: &str| {
+            Ok(match input.trim().parse::<i32>() {
+                Ok(year) if (min_year..=max_year).contains(&year) => Validation::Valid,
+                Ok(year) => Validation::Invalid(format!("No data for {}, choose a year between {} and {}", year, min_year, max_year).into()),
+                Err(_) => Validation::Invalid(format!("Invalid year '{}', expected YYYY", input.trim()).into()),
+            })
+        })
+        .prompt()
+        .unwrap();
+
+    year_input.trim().parse().unwrap()
+}
+
+fn choose_custom_dates(min_date: DateTime<Utc>, max_date: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let help = "YYYY-MM-DD, YYYY-MM-DD HH:MM, YYYY-MM, YYYY, 2020Q2 or relative like -3y";
+
+    // The validator explains why a date is refused
+    // and the prompt keeps asking until a valid one is entered
+    let beginning_input = Text::new(&format!("Enter beginning date, between {} and {}: ", format_date(min_date), format_date(max_date)))
+        .with_help_message(help)
+        .with_validator(move |input: &str| {
+            Ok(match parse_date(input, Bound::Start, max_date).and_then(|date| check_bounds(date, min_date, max_date)) {
+                Ok(_) => Validation::Valid,
+                Err(e) => Validation::Invalid(e.into()),
+            })
+        })
+        .prompt()
+        .unwrap();
+    let beginning_date = parse_date(&beginning_input, Bound::Start, max_date).unwrap();
+
+    let end_input = Text::new(&format!("Enter end date, between {} and {}: ", format_date(beginning_date), format_date(max_date)))
+        .with_help_message(help)
+        .with_validator(move |input: &str| {
+            Ok(match parse_date(input, Bound::End, max_date).and_then(|date| check_range(beginning_date, date, min_date, max_date)) {
+                Ok(_) => Validation::Valid,
+                Err(e) => Validation::Invalid(e.into()),
+            })
+        })
+        .prompt()
+        .unwrap();
+    let end_date = parse_date(&end_input, Bound::End, max_date).unwrap();
+
+    (beginning_date, end_date)
 }
 
 pub fn choose_destination() -> PathBuf {
@@ -133,7 +247,7 @@ pub fn choose_destination() -> PathBuf {
     // Convert the string to a PathBuf
     let path = PathBuf::from(destination);
 
-    let abs_path = match canonicalize(&path) {
+    match canonicalize(&path) {
         Ok(p) => p,
         Err(_) => {
             // Si la canonicalisation échoue (ex: dossier pas encore créé),
@@ -147,15 +261,54 @@ pub fn choose_destination() -> PathBuf {
                 exit(1);
             })
         }
+    }
+}
+
+pub fn choose_sessions() -> SessionOptions {
+    clear_terminal();
+
+    let choices = vec![
+        "All sessions",
+        "All sessions, tagged in a session column",
+        "Sydney",
+        "Tokyo",
+        "London",
+        "New York",
+        "London / New York overlap",
+    ];
+
+    // Create a new select prompt
+    let selection = Select::new()
+        .with_prompt("Select a trading session")
+        .default(0)
+        .items(&choices)
+        .interact()
+        .unwrap();
+
+    let filter = match selection {
+        2 => TradingSession::sydney(),
+        3 => TradingSession::tokyo(),
+        4 => TradingSession::london(),
+        5 => TradingSession::new_york(),
+        6 => TradingSession::london_new_york(),
+        _ => {
+            return SessionOptions {
+                tag: selection == 1,
+                ..Default::default()
+            }
+        }
     };
 
-    return abs_path;
+    SessionOptions {
+        filter: vec![filter],
+        ..Default::default()
+    }
 }
 
-pub fn choose_datatype() -> String {
+pub fn choose_datatype() -> OutputFormat {
     clear_terminal();
 
-    let data_types = vec!["csv", "parquet" ];
+    let data_types = OutputFormat::all();
 
     // Create a new select prompt
     let selection = Select::new()
@@ -165,5 +318,43 @@ pub fn choose_datatype() -> String {
         .interact()
         .unwrap();
 
-    data_types[selection].to_string()
-}
\ No newline at end of file
+    data_types[selection]
+}
+
+// Ask before touching a file written by a previous run
+// The gap and validation reports follow the write mode of the data
+pub fn choose_write_mode(data_dir: &Path, pair: &str, format: OutputFormat) -> WriteMode {
+    let output_path = default_output_path(data_dir, pair, format).filter(|path| path.exists());
+    let mut existing = output_path.iter().cloned().collect::<Vec<PathBuf>>();
+    if report_write_mode(format, WriteMode::ErrorIfExists) == WriteMode::ErrorIfExists {
+        existing.extend(["gaps", "validation"].iter()
+            .map(|name| report_path(data_dir, pair, name))
+            .filter(|path| path.exists()));
+    }
+    if existing.is_empty() {
+        return WriteMode::ErrorIfExists;
+    }
+
+    clear_terminal();
+
+    // MetaTrader and the backtesting presets cannot be read back
+    let mergeable = output_path.is_some() && !matches!(format, OutputFormat::Mt4History | OutputFormat::Mt5Csv | OutputFormat::Backtest(_));
+    let modes = if mergeable {
+        vec![("Merge the new bars into it", WriteMode::Merge), ("Overwrite it", WriteMode::Overwrite)]
+    } else {
+        vec![("Overwrite it", WriteMode::Overwrite)]
+    };
+    let choices = modes.iter().map(|(choice, _)| *choice).chain(["Cancel"]).collect::<Vec<&str>>();
+    let paths = existing.iter().map(|path| path.display().to_string()).collect::<Vec<String>>();
+    let selection = Select::new()
+        .with_prompt(format!("{} already {}", paths.join(", "), if paths.len() == 1 { "exists" } else { "exist" }))
+        .default(0)
+        .items(&choices)
+        .interact()
+        .unwrap();
+
+    match modes.get(selection) {
+        Some((_, mode)) => *mode,
+        None => exit(0),
+    }
+}
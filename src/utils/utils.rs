@@ -1,10 +1,7 @@
-use crate::NUMBER_OF_SIMULTANEOUS_TASKS;
-
 use directories::UserDirs;
 use once_cell::sync::Lazy;
 use std::{
-        fs::{create_dir_all, metadata, remove_file, File},
-        io::{self, copy},
+        fs::metadata,
         net::TcpListener,
         path::Path,
         process::exit,
@@ -12,7 +9,6 @@ use std::{
         time::Duration,
 };
 use tokio::sync::Mutex;
-use zip::ZipArchive;
 
 // Store the port in a static variable
 // This is a global variable that will be used to store the port
@@ -53,54 +49,14 @@ pub async fn find_available_port(from: usize, to: usize) -> usize {
 // Return the download directory of the current user
 // We can't hardcode the path to the download directory because it's different on each system
 pub fn get_download_dir() -> Result<String, String> {
-    if let Some(user_dirs) = UserDirs::new() {
-        if let Some(download_dir) = user_dirs.download_dir() {
-            return Ok(download_dir.to_string_lossy().to_string());
-        }
+    if let Some(user_dirs) = UserDirs::new()
+        && let Some(download_dir) = user_dirs.download_dir() {
+        return Ok(download_dir.to_string_lossy().to_string());
     }
 
     Err("Failed to get download directory".to_string())
 }
 
-// Unzip the file to the wanted directory
-pub fn unzip_file(zip_path: &str) -> io::Result<()> {
-    // Open the zip file
-    // Create a new zip archive
-    let file = File::open(zip_path)?;
-    let mut archive = ZipArchive::new(file)?;
-
-    // Iterate through the files in the archive
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let outpath = Path::new(zip_path.strip_suffix(".zip").unwrap()).join(file.name());
-
-        if (*file.name()).ends_with('/') {
-            // If it's a directory
-            // Create the directory if it doesn't exist
-            create_dir_all(&outpath)?;
-        } else {
-            // if it's a file
-            // Check if the parent directory exists
-            // If it doesn't exist, create it
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    create_dir_all(&p)?;
-                }
-            }
-
-            // Create the file 
-            // Copy the content of the file to the new file
-            let mut outfile = File::create(&outpath)?;
-            copy(&mut file, &mut outfile)?;
-        }
-    }
-
-    // Remove the zip file
-    remove_file(zip_path)?;
-
-    Ok(())
-}
-
 // Function to wait until the file is downloaded
 pub fn wait_until_file_downloaded(path: &str) {
     let mut last_size = 0;
@@ -129,15 +85,15 @@ pub fn wait_until_file_downloaded(path: &str) {
 
 // This function serves use to split the date range into the number of tasks
 // So we can download the data in parallel
-pub fn calculate_split(from_year: usize, year_duration: usize) -> Vec<Vec<usize>> {
+pub fn calculate_split(from_year: usize, year_duration: usize, tasks: usize) -> Vec<Vec<usize>> {
 
     // We calculate the minmum number of years for each task
     // And the surplus years
-    let base = year_duration / NUMBER_OF_SIMULTANEOUS_TASKS;
-    let rest = year_duration % NUMBER_OF_SIMULTANEOUS_TASKS;
+    let base = year_duration / tasks;
+    let rest = year_duration % tasks;
 
     // We calculate the number of years for each task
-    let split: Vec<usize> = (0..NUMBER_OF_SIMULTANEOUS_TASKS)
+    let split: Vec<usize> = (0..tasks)
         .map(|i| if i < rest { base + 1 } else { base })
         .collect();
 
@@ -163,5 +119,5 @@ pub fn calculate_progress_weight(year_duration: usize) -> usize {
     // And +1 because we need to save the data
     let progress_weight = year_duration * 2 + 1;
 
-    (100 / progress_weight) as usize
+    100 / progress_weight
 }
\ No newline at end of file